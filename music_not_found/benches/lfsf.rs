@@ -0,0 +1,44 @@
+//! Compares the LFSF with its sparse, cached mel filterbank to the previous implementation, which
+//! created the dense filterbank on every call and multiplied every bin with every filter.
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustfft::num_complex::Complex;
+
+use music_not_found::constants::MEL_BANDS;
+use music_not_found::helpers::{Padding, WindowFunction};
+use music_not_found::onset_algorithms::{mel_filterbank, OnsetAlgorithm, OnsetInput, LFSF};
+use music_not_found::test_signals::TestSignal;
+use music_not_found::track::Downmix;
+
+/// The LFSF as it was implemented before: dense filterbank, created on every call
+fn dense_lfsf(input: &OnsetInput, log_lambda: f32) -> Vec<f32> {
+    let filterbank = mel_filterbank(input.sampling_rate, input.stft.window_size, MEL_BANDS, None, None);
+    let log_mel = |frame: &Vec<Complex<f32>>| -> Vec<f32> {
+        filterbank
+            .iter()
+            .map(|mel_frame| frame.iter().zip(mel_frame.iter()).map(|(x, weight)| x.norm() * weight).sum())
+            .map(|x: f32| (x * log_lambda + 1.).log10())
+            .collect()
+    };
+    let data: Vec<Vec<f32>> = input.stft.data.iter().map(log_mel).collect();
+    (0..data.len())
+        .map(|i| if i == 0 { LFSF::flux(&data[0], &vec![0.; MEL_BANDS]) } else { LFSF::flux(&data[i], &data[i - 1]) })
+        .collect()
+}
+
+fn lfsf(c: &mut Criterion) {
+    let (track, _) = TestSignal::drum_pattern(120., 10.).generate();
+    for (window_size, hop_size) in [(1024, 441), (2048, 1024)] {
+        let input = OnsetInput::from_track(&track, Downmix::Mid, window_size, hop_size, WindowFunction::Hamming, Padding::Zero);
+        let lfsf = LFSF { log_lambda: 0.7, ..LFSF::default() };
+
+        let mut group = c.benchmark_group(format!("lfsf {}/{}", window_size, hop_size));
+        group.bench_function("dense", |b| b.iter(|| dense_lfsf(&input, 0.7)));
+        group.bench_function("sparse cached", |b| b.iter(|| lfsf.find_onsets(&input)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, lfsf);
+criterion_main!(benches);
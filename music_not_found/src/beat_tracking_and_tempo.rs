@@ -1,6 +1,9 @@
+use std::collections::VecDeque;
+
 use arima::acf::acf;
 
-use crate::{helpers::WinVec, track::Track};
+use crate::error::{Error, Result};
+use crate::helpers::WinVec;
 use crate::constants::*;
 
 // this is the auto-correlation-function
@@ -12,29 +15,34 @@ pub struct Tempo {
 }
 
 /// This is the tempo estimation function
-pub fn get_tempo(track: &Track, detection_output: &WinVec<f32>) -> (Tempo, Tempo) {
+pub fn get_tempo(detection_output: &WinVec<f32>) -> Result<(Tempo, Tempo)> {
+    let sample_rate = detection_output.sample_rate;
     let mut times: Vec<f64> = Vec::new();
-    let mut a_corr: Vec<f64> = Vec::new();
 
     // Convert the found onset times from f32 to 64
     for &onset_time in detection_output.data.iter() {
         times.push(onset_time as f64);
     }
 
-    let times_slice: &[f64] = times.as_slice(); // The auto-correlation function does not like vectors
-    match acf(times_slice, None, false) {
-        Ok(auto_corr) => a_corr = auto_corr,
-        Err(e) => println!("{:?}", e),
-    };
-
     // For the lowest and highest possible BPM compute its lag (= nr of STFT vectors between two beats)
-    let high = bpm_to_lag(track, detection_output.hop_size, SLOWEST_BPM);
-    let low = bpm_to_lag(track, detection_output.hop_size, HIGHEST_BPM);
+    let high = bpm_to_lag(sample_rate, detection_output.hop_size, SLOWEST_BPM);
+    let low = bpm_to_lag(sample_rate, detection_output.hop_size, HIGHEST_BPM);
+
+    // The auto-correlation has as many lags as the detection function has values
+    if times.len() <= high {
+        return Err(Error::TrackTooShort);
+    }
+
+    let times_slice: &[f64] = times.as_slice(); // The auto-correlation function does not like vectors
+    let a_corr = acf(times_slice, None, false).map_err(|e| Error::TempoEstimation(format!("{:?}", e)))?;
 
 
     // Crop the autocorrelated signal to the area between lowest lag (-> BPM 200) and highest lag (-> BPM 60)
-    let tempo_area = &a_corr[low..high];
+    Ok(strongest_tempi(&a_corr[low..high], low, sample_rate, detection_output.hop_size))
+}
 
+/// Finds the two highest values in the auto-correlation cropped to the lags starting at `low`
+fn strongest_tempi(tempo_area: &[f64], low: usize, sample_rate: u32, hop_size: usize) -> (Tempo, Tempo) {
     let mut max = 0;
     let mut max2 = 0;
 
@@ -53,15 +61,99 @@ pub fn get_tempo(track: &Track, detection_output: &WinVec<f32>) -> (Tempo, Tempo
     (
         Tempo {
             lag: low + max,
-            bpm: lag_to_bpm(track, detection_output.hop_size, low + max),
+            bpm: lag_to_bpm(sample_rate, hop_size, low + max),
         },
         Tempo {
             lag: low + max2,
-            bpm: lag_to_bpm(track, detection_output.hop_size, low + max2),
+            bpm: lag_to_bpm(sample_rate, hop_size, low + max2),
         },
     )
 }
 
+/// Estimates the tempo like get_tempo while the detection function is computed, without keeping
+/// it: only the first and the last `high` values and one sum of products per lag are stored, so
+/// memory does not grow with the track.
+pub struct TempoEstimator {
+    sample_rate: u32,
+    hop_size: usize,
+    low: usize,
+    high: usize,
+    /// The first `high` values of the detection function
+    first: Vec<f64>,
+    /// The last `high` values of the detection function, the oldest first
+    recent: VecDeque<f64>,
+    /// Sum of `x[i] * x[i + lag]` for every lag from `low` to `high`
+    products: Vec<f64>,
+    sum: f64,
+    count: usize,
+}
+
+impl TempoEstimator {
+    pub fn new(sample_rate: u32, hop_size: usize) -> TempoEstimator {
+        let high = bpm_to_lag(sample_rate, hop_size, SLOWEST_BPM);
+        let low = bpm_to_lag(sample_rate, hop_size, HIGHEST_BPM);
+        TempoEstimator {
+            sample_rate,
+            hop_size,
+            low,
+            high,
+            first: Vec::with_capacity(high),
+            recent: VecDeque::with_capacity(high + 1),
+            products: vec![0.; high - low],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    /// Adds the next value of the detection function
+    pub fn push(&mut self, value: f32) {
+        let value = value as f64;
+        for (lag, product) in (self.low..self.high).zip(self.products.iter_mut()) {
+            if lag <= self.recent.len() {
+                *product += value * self.recent[self.recent.len() - lag];
+            }
+        }
+        if self.first.len() < self.high {
+            self.first.push(value);
+        }
+        self.recent.push_back(value);
+        if self.recent.len() > self.high {
+            self.recent.pop_front();
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Number of detection function values that are stored, independent of the number of pushed values
+    #[cfg(test)]
+    pub fn stored_values(&self) -> usize {
+        self.first.len() + self.recent.len() + self.products.len()
+    }
+
+    /// The two most likely tempi of the values pushed so far (see get_tempo)
+    pub fn tempo(&self) -> Result<(Tempo, Tempo)> {
+        // The auto-correlation has as many lags as the detection function has values
+        if self.count <= self.high {
+            return Err(Error::TrackTooShort);
+        }
+
+        // The auto-covariance of the values without their mean, computed from the sums: the
+        // products of the lag minus the mean times the values of both overlapping ranges
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        let mut sum_first = self.first[..self.low].iter().sum::<f64>();
+        let mut sum_last = self.recent.iter().rev().take(self.low).sum::<f64>();
+        let mut covariance = Vec::with_capacity(self.products.len());
+        for (lag, product) in (self.low..self.high).zip(&self.products) {
+            let overlap = (self.sum - sum_last) + (self.sum - sum_first);
+            covariance.push((product - mean * overlap + (n - lag as f64) * mean * mean) / n);
+            sum_first += self.first[lag];
+            sum_last += self.recent[self.recent.len() - 1 - lag];
+        }
+        Ok(strongest_tempi(&covariance, self.low, self.sample_rate, self.hop_size))
+    }
+}
+
 /// Data structure containing found beats
 pub struct Beats {
     /// A list of beat times (in seconds)
@@ -69,9 +161,13 @@ pub struct Beats {
 }
 
 /// The beat detection function
-pub fn get_beats(tempo: Tempo, onset_times: &Vec<f64>, first_beat_index: usize) -> Beats {
+pub fn get_beats(tempo: Tempo, onset_times: &Vec<f64>, first_beat_index: Option<usize>) -> Result<Beats> {
     let mut beats: Vec<f64> = Vec::new();
 
+    let first_beat_index = first_beat_index
+        .filter(|&index| index < onset_times.len())
+        .ok_or(Error::NotEnoughPeaks)?;
+
     let beat_period = 1. / tempo.bpm * 60.; // Compute the average time duration between two beats
 
     beats.push(onset_times[first_beat_index]); // The first local maxima of the onsets is set as the first beat.
@@ -80,7 +176,7 @@ pub fn get_beats(tempo: Tempo, onset_times: &Vec<f64>, first_beat_index: usize)
     let mut i = first_beat_index + 1; // set the index of the onset of the first beat (starting point for iteration over all onset times)
 
     // Iterate over the onset times
-    while i < onset_times.len() - 2 {
+    while i + 2 < onset_times.len() {
         // take the next two onsets following the last identified beat and treat them as "next beats"
         let next1: f64 = onset_times[i];
         let next2 = onset_times[i + 1];
@@ -109,22 +205,22 @@ pub fn get_beats(tempo: Tempo, onset_times: &Vec<f64>, first_beat_index: usize)
         i += 1;
     }
 
-    Beats { beats }
+    Ok(Beats { beats })
 }
 
 /// Convert BPM into a number of frequency vectors that lay between two beats
-fn bpm_to_lag(track: &Track, hop_size: usize, bpm: f64) -> usize {
-    let sample_rate = 1. / (track.header.sample_rate as f64);
-    let delta = sample_rate * (hop_size as f64);
+fn bpm_to_lag(sample_rate: u32, hop_size: usize, bpm: f64) -> usize {
+    let sample_period = 1. / (sample_rate as f64);
+    let delta = sample_period * (hop_size as f64);
     let bps = bpm / 60.;
     let beat_periode = 1. / bps;
     (beat_periode / delta) as usize
 }
 
 /// the inverse of bpm_to_lag
-fn lag_to_bpm(track: &Track, hop_size: usize, lag: usize) -> f64 {
-    let sample_rate = 1. / (track.header.sample_rate as f64);
-    let delta = sample_rate * (hop_size as f64);
+fn lag_to_bpm(sample_rate: u32, hop_size: usize, lag: usize) -> f64 {
+    let sample_period = 1. / (sample_rate as f64);
+    let delta = sample_period * (hop_size as f64);
     let beat_periode = lag as f64 * delta;
     let bps = 1. / beat_periode;
     bps * 60.
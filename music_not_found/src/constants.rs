@@ -1,9 +1,32 @@
 /// Defines how many tracks are processed in parallel
 pub const NO_THREADS: usize = 12;
 
+/// The sample rate (in Hz) all tracks are resampled to before analysis. Window and hop sizes as
+/// well as the peak picking parameters are tuned for this rate.
+pub const ANALYSIS_SAMPLE_RATE: u32 = 44100;
+
+/// Number of input samples the resampler processes at once
+pub const RESAMPLER_CHUNK_SIZE: usize = 4096;
+
 /// The number of Mel Bands used at LFSF
 pub const MEL_BANDS: usize = 128;
 
+/// The logarithmic compression of the LFSF of the submission, also the default of `--onset lfsf`
+pub const LFSF_LOG_LAMBDA: f32 = 0.7;
+
+/// Added to the previous magnitude in the modified Kullback-Leibler distance, so that bins
+/// rising from silence do not divide by zero
+pub const MKL_EPSILON: f32 = 1e-6;
+
+/// Boundary (in Hz) between the low and the mid band group of multi-band onset detection
+pub const BAND_LOW_MID_FREQUENCY: f32 = 200.;
+/// Boundary (in Hz) between the mid and the high band group of multi-band onset detection
+pub const BAND_MID_HIGH_FREQUENCY: f32 = 5000.;
+
+/// Energy added before taking the logarithm in the log-energy envelope, so that (almost) silent
+/// frames do not cause huge differences (about -60 dB)
+pub const LOG_ENERGY_FLOOR: f32 = 1e-6;
+
 
 /* For validation of results */
 /// Accuracy in seconds of the estimated onsets
@@ -21,6 +44,16 @@ pub const SLOWEST_BPM: f64 = 60.;
 pub const HIGHEST_BPM: f64 = 200.;
 
 
+/* For real-time detection */
+/// Time (in seconds) between two tempo estimations
+pub const REALTIME_TEMPO_INTERVAL: f64 = 1.;
+/// Length (in seconds) of the most recent part of the stream the tempo is estimated on
+pub const REALTIME_TEMPO_WINDOW: f64 = 8.;
+/// Maximum distance of an onset to the expected next beat (as fraction of the beat period) to
+/// be taken as beat
+pub const REALTIME_BEAT_TOLERANCE: f64 = 0.2;
+
+
 /// Parameter that describes how onset times of different algorithms are combined.
 /// 1 means an onset time needs to be found by all onset algorithms,
 /// 0.5 means half of the used onset algorithms need to have an onset found in order to count it
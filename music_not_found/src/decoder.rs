@@ -0,0 +1,327 @@
+use std::{fs::File, io::Read, path::Path};
+
+use symphonia::core::{
+    audio::{AudioBuffer, AudioBufferRef, Signal},
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    sample::Sample,
+};
+
+use crate::error::{Error, Result};
+
+/// File extensions (lower case) of all audio formats that can be decoded, with their format
+pub const SUPPORTED_EXTENSIONS: [(&str, AudioFormat); 9] = [
+    ("wav", AudioFormat::Wav),
+    ("wave", AudioFormat::Wav),
+    ("flac", AudioFormat::Flac),
+    ("ogg", AudioFormat::OggVorbis),
+    ("oga", AudioFormat::OggVorbis),
+    ("mp3", AudioFormat::Mp3),
+    ("aiff", AudioFormat::Aiff),
+    ("aif", AudioFormat::Aiff),
+    ("aifc", AudioFormat::Aiff),
+];
+
+/// The audio formats that can be read
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+    OggVorbis,
+    Mp3,
+    Aiff,
+}
+
+impl AudioFormat {
+    /// Determines the format of the file at the given path. The magic bytes at the start of the
+    /// file are checked first; if they are not conclusive, the file extension decides.
+    pub fn detect(file_path: &Path) -> Option<AudioFormat> {
+        let mut magic = [0u8; 12];
+        let read = File::open(file_path)
+            .and_then(|mut file| file.read(&mut magic))
+            .unwrap_or(0);
+
+        AudioFormat::from_magic_bytes(&magic[..read]).or_else(|| AudioFormat::from_extension(file_path))
+    }
+
+    /// Determines the format from the first bytes of a file
+    pub fn from_magic_bytes(bytes: &[u8]) -> Option<AudioFormat> {
+        if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+            Some(AudioFormat::Wav)
+        } else if bytes.len() >= 12 && &bytes[0..4] == b"FORM" && (&bytes[8..12] == b"AIFF" || &bytes[8..12] == b"AIFC") {
+            Some(AudioFormat::Aiff)
+        } else if bytes.starts_with(b"fLaC") {
+            Some(AudioFormat::Flac)
+        } else if bytes.starts_with(b"OggS") {
+            Some(AudioFormat::OggVorbis)
+        } else if bytes.starts_with(b"ID3") || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0) {
+            // either an ID3 tag or directly an MPEG frame sync
+            Some(AudioFormat::Mp3)
+        } else {
+            None
+        }
+    }
+
+    /// Determines the format from the extension of the given path
+    pub fn from_extension(file_path: &Path) -> Option<AudioFormat> {
+        let extension = file_path.extension()?.to_str()?.to_lowercase();
+        SUPPORTED_EXTENSIONS
+            .iter()
+            .find(|(supported, _)| *supported == extension)
+            .map(|&(_, format)| format)
+    }
+
+    /// Returns the decoder that is able to read this format
+    pub fn decoder(&self) -> Box<dyn Decoder> {
+        match self {
+            AudioFormat::Wav => Box::new(WavDecoder),
+            _ => Box::new(SymphoniaDecoder { format: *self }),
+        }
+    }
+
+    /// Extension that is passed to symphonia as hint
+    fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+            AudioFormat::OggVorbis => "ogg",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Aiff => "aiff",
+        }
+    }
+}
+
+/// The decoded content of an audio file
+pub struct DecodedAudio {
+    /// The samples of each channel (not interleaved)
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: u32,
+}
+
+/// Defines an interface for reading audio files of a specific format
+pub trait Decoder {
+    fn decode(&self, file_path: &Path) -> Result<DecodedAudio>;
+}
+
+/// Reads WAV files using wav_io
+pub struct WavDecoder;
+
+impl Decoder for WavDecoder {
+    fn decode(&self, file_path: &Path) -> Result<DecodedAudio> {
+        let input_file = File::open(file_path)?;
+        let (header, samples) = wav_io::read_from_file(input_file).map_err(|e| Error::Decode(format!("{:?}", e)))?;
+        Ok(DecodedAudio {
+            channels: deinterleave(&samples, header.channels as usize),
+            sample_rate: header.sample_rate,
+        })
+    }
+}
+
+/// Reads FLAC, OGG Vorbis, MP3 and AIFF files using symphonia
+pub struct SymphoniaDecoder {
+    pub format: AudioFormat,
+}
+
+impl Decoder for SymphoniaDecoder {
+    fn decode(&self, file_path: &Path) -> Result<DecodedAudio> {
+        let mut stream = PacketStream::open(file_path, self.format)?;
+        let mut channels: Vec<Vec<f32>> = vec![Vec::new(); stream.channels as usize];
+
+        while let Some(block) = stream.next_block()? {
+            channels.resize(block.len(), Vec::new());
+            for (channel, block_channel) in channels.iter_mut().zip(block) {
+                channel.extend(block_channel);
+            }
+        }
+
+        Ok(DecodedAudio {
+            channels,
+            sample_rate: stream.sample_rate,
+        })
+    }
+}
+
+/// Reads an audio file of any supported format packet by packet using symphonia, so that files
+/// can be processed without holding them in memory completely
+pub struct PacketStream {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    /// Integer samples are converted the way wav_io does it, so WAV files decode to the same
+    /// values as with the WavDecoder
+    wav_io_scaling: bool,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl PacketStream {
+    /// Opens the file at the given path and prepares the decoder of its first audio track
+    pub fn open(file_path: &Path, format: AudioFormat) -> Result<PacketStream> {
+        let input_file = File::open(file_path)?;
+        let stream = MediaSourceStream::new(Box::new(input_file), Default::default());
+
+        let mut hint = Hint::new();
+        hint.with_extension(format.extension());
+
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?;
+        let reader = probed.format;
+
+        // Only the first decodable track of the file is read
+        let track = reader
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| Error::Decode("file does not contain an audio track".to_owned()))?;
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        Ok(PacketStream {
+            track_id: track.id,
+            sample_rate: track.codec_params.sample_rate.unwrap_or(0),
+            channels: track.codec_params.channels.map(|c| c.count() as u16).unwrap_or(0),
+            wav_io_scaling: format == AudioFormat::Wav,
+            reader,
+            decoder,
+        })
+    }
+
+    /// Decodes the next packet of the file. Returns the samples of each channel, or None if the
+    /// end of the file has been reached.
+    pub fn next_block(&mut self) -> Result<Option<Vec<Vec<f32>>>> {
+        loop {
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                // symphonia signals the end of the stream through an EOF error
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(buffer) => {
+                    self.sample_rate = buffer.spec().rate;
+                    self.channels = buffer.spec().channels.count() as u16;
+                    return Ok(Some(convert(buffer, self.wav_io_scaling)));
+                }
+                // A corrupt packet is skipped, the remaining ones may still be fine
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+}
+
+/// Converts a decoded buffer to f32 samples of each channel. With wav_io_scaling, integer samples
+/// are converted the way wav_io does it.
+fn convert(buffer: AudioBufferRef, wav_io_scaling: bool) -> Vec<Vec<f32>> {
+    fn channels<S: Sample>(buffer: &AudioBuffer<S>, convert: impl Fn(S) -> f32) -> Vec<Vec<f32>> {
+        (0..buffer.spec().channels.count())
+            .map(|channel| buffer.chan(channel).iter().map(|&x| convert(x)).collect())
+            .collect()
+    }
+
+    if wav_io_scaling {
+        match &buffer {
+            AudioBufferRef::U8(b) => return channels(b, |x| x.wrapping_sub(128) as i8 as f32 / (0xFF as f32 / 2.0)),
+            AudioBufferRef::S16(b) => return channels(b, |x| x as f32 / (0xFFFF as f32 / 2.0)),
+            AudioBufferRef::S24(b) => return channels(b, |x| x.inner() as f32 / (0xFFFFFF as f32 / 2.0)),
+            AudioBufferRef::S32(b) => return channels(b, |x| x as f32 / (0xFFFFFFFFu32 as f32 / 2.0)),
+            AudioBufferRef::F64(b) => return channels(b, |x| x as f32),
+            _ => {}
+        }
+    }
+
+    let mut converted = buffer.make_equivalent::<f32>();
+    buffer.convert(&mut converted);
+    channels(&converted, |x| x)
+}
+
+/// Splits interleaved samples into their channels. An incomplete frame at the end is dropped, so
+/// all channels have the same length.
+pub fn deinterleave(samples: &[f32], channel_count: usize) -> Vec<Vec<f32>> {
+    let channel_count = channel_count.max(1);
+    let frames = samples.len() / channel_count;
+    (0..channel_count)
+        .map(|channel| samples.iter().skip(channel).step_by(channel_count).take(frames).cloned().collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::track::Track;
+
+    /// Writes the given bytes to a file in the temporary directory
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("music_not_found_decoder_test_{}", name));
+        File::create(&path).unwrap().write_all(bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn formats_are_detected_by_magic_bytes() {
+        let cases: [(&[u8], Option<AudioFormat>); 10] = [
+            (b"RIFF\x24\0\0\0WAVEfmt ", Some(AudioFormat::Wav)),
+            (b"FORM\0\0\0\x24AIFFCOMM", Some(AudioFormat::Aiff)),
+            (b"FORM\0\0\0\x24AIFCFVER", Some(AudioFormat::Aiff)),
+            (b"fLaC\0\0\0\x22", Some(AudioFormat::Flac)),
+            (b"OggS\0\x02", Some(AudioFormat::OggVorbis)),
+            (b"ID3\x04\0", Some(AudioFormat::Mp3)),
+            (&[0xFF, 0xFB, 0x90, 0x64], Some(AudioFormat::Mp3)),
+            // RIFF containers of other types and truncated headers are not conclusive
+            (b"RIFF\x24\0\0\0AVI LIST", None),
+            (b"RIFF\x24\0\0\0WAV", None),
+            (b"", None),
+        ];
+        for (bytes, format) in cases {
+            assert_eq!(AudioFormat::from_magic_bytes(bytes), format, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn extension_decides_if_magic_bytes_are_not_conclusive() {
+        assert_eq!(AudioFormat::from_extension(Path::new("a/track.FLAC")), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::from_extension(Path::new("track.aif")), Some(AudioFormat::Aiff));
+        assert_eq!(AudioFormat::from_extension(Path::new("track.aifc")), Some(AudioFormat::Aiff));
+        assert_eq!(AudioFormat::from_extension(Path::new("track.WAVE")), Some(AudioFormat::Wav));
+        assert_eq!(AudioFormat::from_extension(Path::new("track.txt")), None);
+        assert_eq!(AudioFormat::from_extension(Path::new("track")), None);
+
+        // Magic bytes win over the extension; without them, the extension is used
+        let wav_named_mp3 = temp_file("wav.mp3", b"RIFF\x24\0\0\0WAVEfmt ");
+        assert_eq!(AudioFormat::detect(&wav_named_mp3), Some(AudioFormat::Wav));
+        let unknown_ogg = temp_file("unknown.ogg", b"not audio at all");
+        assert_eq!(AudioFormat::detect(&unknown_ogg), Some(AudioFormat::OggVorbis));
+        assert_eq!(AudioFormat::detect(Path::new("does/not/exist.mp3")), Some(AudioFormat::Mp3));
+        std::fs::remove_file(wav_named_mp3).unwrap();
+        std::fs::remove_file(unknown_ogg).unwrap();
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        let path = temp_file("unknown.xyz", b"not audio at all");
+        assert!(matches!(Track::from_path(&path), Err(Error::UnsupportedFormat)));
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(Track::from_path(Path::new("does/not/exist.xyz")), Err(Error::UnsupportedFormat)));
+    }
+
+    #[test]
+    fn deinterleave_drops_incomplete_frames() {
+        let samples = [1., -1., 2., -2., 3., -3., 4.];
+        assert_eq!(deinterleave(&samples, 2), vec![vec![1., 2., 3.], vec![-1., -2., -3.]]);
+        assert_eq!(deinterleave(&samples[..6], 3), vec![vec![1., -2.], vec![-1., 3.], vec![2., -3.]]);
+        assert_eq!(deinterleave(&samples, 1), vec![samples.to_vec()]);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::offset_detection::OffsetDetector;
+use crate::onset_algorithms::{BandGroup, OnsetInput};
+use crate::peak_picking::Onset;
+
+/// Kind of percussive sound that starts at an onset
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrumClass {
+    Kick,
+    Snare,
+    HiHat,
+    /// Everything else, e.g. tonal notes
+    Other,
+}
+
+impl DrumClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DrumClass::Kick => "kick",
+            DrumClass::Snare => "snare",
+            DrumClass::HiHat => "hi-hat",
+            DrumClass::Other => "other",
+        }
+    }
+}
+
+/// Hand-designed spectral features of the sound that starts at an onset
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrumFeatures {
+    /// Shares of the energy in the low, mid and high band group (`BandGroup::low_mid_high`) during
+    /// the feature time after the onset; they add up to 1
+    pub low: f32,
+    pub mid: f32,
+    pub high: f32,
+    /// Spectral centroid (in Hz) during the feature time after the onset
+    pub centroid: f32,
+    /// Time (in seconds) until the sound has decayed, see `OffsetDetector`
+    pub decay_time: f64,
+}
+
+/// Rule-based classification of onsets into kick, snare and hi-hat by their band energy ratios,
+/// spectral centroid and decay time. Kicks have most of their energy in the low band and decay
+/// fast, hi-hats have almost all of their energy in the high band, and snares mix a tone in the
+/// mid band with broadband noise. Short bass notes look like kicks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrumClassifier {
+    /// Time (in seconds) after an onset over which the band energies and the centroid are measured
+    pub feature_time: f64,
+    /// Finds the decay times
+    pub offsets: OffsetDetector,
+    /// Minimum share of the low band and maximum decay time (in seconds) of a kick
+    pub kick_low_share: f32,
+    pub kick_decay_time: f64,
+    /// Minimum share of the high band and minimum centroid (in Hz) of a hi-hat
+    pub hi_hat_high_share: f32,
+    pub hi_hat_centroid: f32,
+    /// Minimum shares of the mid and of the high band of a snare
+    pub snare_mid_share: f32,
+    pub snare_high_share: f32,
+}
+
+impl Default for DrumClassifier {
+    fn default() -> DrumClassifier {
+        DrumClassifier {
+            feature_time: 0.03,
+            offsets: OffsetDetector::default(),
+            kick_low_share: 0.5,
+            kick_decay_time: 0.5,
+            hi_hat_high_share: 0.8,
+            hi_hat_centroid: 10000.,
+            snare_mid_share: 0.1,
+            snare_high_share: 0.3,
+        }
+    }
+}
+
+impl DrumClassifier {
+    /// Classifies onsets (sorted by time) by the features of the input around their frames. Onset
+    /// times are relative to the start of the file, which is at start_time of the input.
+    pub fn classify(&self, input: &OnsetInput, onsets: &[Onset], start_time: f64) -> Vec<DrumClass> {
+        self.features(input, onsets, start_time)
+            .iter()
+            .map(|features| self.class_of(features))
+            .collect()
+    }
+
+    /// Applies the rules to the features of a single onset
+    pub fn class_of(&self, features: &DrumFeatures) -> DrumClass {
+        if features.low >= self.kick_low_share && features.decay_time <= self.kick_decay_time {
+            DrumClass::Kick
+        } else if features.high >= self.hi_hat_high_share && features.centroid >= self.hi_hat_centroid {
+            DrumClass::HiHat
+        } else if features.mid >= self.snare_mid_share && features.high >= self.snare_high_share {
+            DrumClass::Snare
+        } else {
+            DrumClass::Other
+        }
+    }
+
+    /// Computes the features of every onset
+    pub fn features(&self, input: &OnsetInput, onsets: &[Onset], start_time: f64) -> Vec<DrumFeatures> {
+        let frames = &input.stft;
+        let last_frame = frames.data.len().saturating_sub(1);
+        let feature_frames = (self.feature_time * input.sampling_rate as f64 / frames.hop_size as f64).round() as usize;
+        let bin_width = input.sampling_rate as f32 / frames.window_size as f32;
+        let groups = BandGroup::low_mid_high();
+
+        let notes = self.offsets.notes(input, onsets, start_time);
+        notes
+            .iter()
+            .map(|note| {
+                let onset = frames.frame_at(note.onset.time - start_time).min(last_frame);
+                let mut energies = [0f32; 3];
+                let mut weighted_frequencies = 0.;
+                for frame in &frames.data[onset..=(onset + feature_frames).min(last_frame)] {
+                    for (bin, x) in frame.iter().enumerate() {
+                        let frequency = bin as f32 * bin_width;
+                        let energy = x.norm_sqr();
+                        if let Some(group) = groups.iter().position(|group| group.contains(frequency)) {
+                            energies[group] += energy;
+                        }
+                        weighted_frequencies += frequency * energy;
+                    }
+                }
+                let total = energies.iter().sum::<f32>().max(f32::MIN_POSITIVE);
+                DrumFeatures {
+                    low: energies[0] / total,
+                    mid: energies[1] / total,
+                    high: energies[2] / total,
+                    centroid: weighted_frequencies / total,
+                    decay_time: note.duration(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::test_signals::{Sound, TestSignal};
+    use crate::track::Downmix;
+
+    fn truth_onsets(times: &[f64]) -> Vec<Onset> {
+        times
+            .iter()
+            .map(|&time| Onset { time, strength: 1., salience: 1., agreement: 1 })
+            .collect()
+    }
+
+    #[test]
+    fn drum_pattern_is_classified() {
+        let (track, truth) = TestSignal::drum_pattern(120., 10.).with_swing(0.6).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let classes = DrumClassifier::default().classify(&input, &truth_onsets(&truth.onsets), 0.);
+        assert_eq!(classes.len(), truth.sounds.len());
+        for (class, sound) in classes.iter().zip(&truth.sounds) {
+            let expected = match sound {
+                Sound::Kick => DrumClass::Kick,
+                Sound::Snare => DrumClass::Snare,
+                Sound::HiHat => DrumClass::HiHat,
+                _ => DrumClass::Other,
+            };
+            assert_eq!(*class, expected);
+        }
+    }
+
+    #[test]
+    fn tones_are_no_drums() {
+        let (track, truth) = TestSignal::tone_bursts(120., 6., &[440., 880., 3000.]).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let classes = DrumClassifier::default().classify(&input, &truth_onsets(&truth.onsets), 0.);
+        assert!(classes.iter().all(|&class| class == DrumClass::Other), "{:?}", classes);
+    }
+}
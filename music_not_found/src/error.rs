@@ -0,0 +1,90 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Errors that may occur while analysing a track
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be opened or read
+    Io(std::io::Error),
+    /// The format of the audio file is not supported
+    UnsupportedFormat,
+    /// The audio file could not be decoded
+    Decode(String),
+    /// The audio file does not contain any samples
+    EmptyTrack,
+    /// The track could not be resampled to the analysis sample rate
+    Resample(String),
+    /// A line of a ground truth file is not a valid time
+    GroundTruth { line: usize, content: String },
+    /// Peak picking found too few peaks to determine the first beat
+    NotEnoughPeaks,
+    /// The track is too short for the analysis, e.g. to estimate its tempo
+    TrackTooShort,
+    /// The auto-correlation for the tempo estimation failed
+    TempoEstimation(String),
+    /// The chosen options can not be used together
+    InvalidOptions(String),
+}
+
+/// Result type of all fallible functions of the analysis pipeline
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "could not read file: {}", e),
+            Error::UnsupportedFormat => write!(f, "unsupported audio format"),
+            Error::Decode(message) => write!(f, "could not decode audio: {}", message),
+            Error::EmptyTrack => write!(f, "the track does not contain any samples"),
+            Error::Resample(message) => write!(f, "could not resample track: {}", message),
+            Error::GroundTruth { line, content } => {
+                write!(f, "invalid time in line {} of ground truth file: {:?}", line, content)
+            }
+            Error::NotEnoughPeaks => write!(f, "too few peaks found to determine the first beat"),
+            Error::TrackTooShort => write!(f, "the track is too short for the analysis"),
+            Error::TempoEstimation(message) => write!(f, "could not estimate tempo: {}", message),
+            Error::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<rubato::ResamplerConstructionError> for Error {
+    fn from(e: rubato::ResamplerConstructionError) -> Error {
+        Error::Resample(e.to_string())
+    }
+}
+
+impl From<rubato::ResampleError> for Error {
+    fn from(e: rubato::ResampleError) -> Error {
+        Error::Resample(e.to_string())
+    }
+}
+
+impl From<glob::GlobError> for Error {
+    fn from(e: glob::GlobError) -> Error {
+        // The message of the glob error names the path that could not be read
+        Error::Io(std::io::Error::new(e.error().kind(), e.to_string()))
+    }
+}
+
+impl From<glob::PatternError> for Error {
+    fn from(e: glob::PatternError) -> Error {
+        Error::InvalidOptions(format!("invalid folder pattern: {}", e))
+    }
+}
+
+impl From<symphonia::core::errors::Error> for Error {
+    fn from(e: symphonia::core::errors::Error) -> Error {
+        match e {
+            symphonia::core::errors::Error::IoError(e) => Error::Io(e),
+            e => Error::Decode(e.to_string()),
+        }
+    }
+}
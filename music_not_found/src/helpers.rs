@@ -1,69 +1,306 @@
+use std::f32::consts::PI;
 use std::iter::repeat;
+use std::sync::{Arc, Mutex, OnceLock};
 
 use dsp::window;
-use rustfft::{FftPlanner, num_complex::Complex};
+use realfft::{RealFftPlanner, RealToComplex};
+use rustfft::num_complex::Complex;
 
 /// Creates vectors of given length only containing zeroes
 pub fn zeroes(n: usize) -> Vec<f32> {
     repeat(0.).take(n).collect()
 }
 
-/// Computes the stft of the given signal, using the given window and hop-size
-pub fn stft(signal: &[f32], window_size: usize, hop_size: usize) -> WinVec<Vec<Complex<f32>>> {
-    let mut planner = FftPlanner::new();
-    let hamming = window::hamming(window_size);
+/// Computes the stft of the given signal, using the given window function, window and hop-size.
+/// Frames are centred: frame i is centred at sample i * hop_size, so the signal is padded by half a
+/// window at both ends and there are 1 + len / hop_size frames (also for signals shorter than a
+/// window). Each frame holds the window_size / 2 + 1 bins up to the Nyquist frequency.
+pub fn stft(
+    signal: &[f32],
+    sample_rate: u32,
+    window_size: usize,
+    hop_size: usize,
+    window_function: WindowFunction,
+    padding: Padding,
+) -> WinVec<Vec<Complex<f32>>> {
+    let transform = FrameTransform::new(window_size, window_function);
 
-    let fft = planner.plan_fft_forward(window_size);
+    let mut padded = padding.before(signal, window_size / 2);
+    padded.extend_from_slice(signal);
+    padded.extend(padding.after(signal, window_size - window_size / 2));
 
-    let mut stft = Vec::new();  // Vector containing the computed FFTs
+    // Compute FFTs of the window size; frame i starts at i * hop_size of the padded signal
+    let stft = (0..=signal.len() / hop_size)
+        .map(|i| transform.transform(&padded[i * hop_size..i * hop_size + window_size]))
+        .collect();
+
+    WinVec {
+        data: stft,
+        window_size,
+        hop_size,
+        window_function,
+        sample_rate,
+    }
+}
+
+/// How the signal is extended before its start and after its end, so that the first and last
+/// stft frames can be centred at the first and last sample
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// Silence
+    Zero,
+    /// The signal mirrored at its first/last sample (without repeating it)
+    Reflect,
+}
 
-    let mut cur_pos: usize = 0;
-    // Compute FFTs of the window size; at the end of iteration shift the window (hop-size!)
-    while cur_pos + window_size < signal.len() {
-        let mut fft_buffer_real = vec![0f32; window_size];
-        let fft_in = &signal[cur_pos..cur_pos + window_size];
+impl Padding {
+    /// Parses the name of a padding as used on the command line: `zero` or `reflect`
+    pub fn from_name(name: &str) -> Option<Padding> {
+        match name {
+            "zero" => Some(Padding::Zero),
+            "reflect" => Some(Padding::Reflect),
+            _ => None,
+        }
+    }
+
+    /// The n padding samples before the start of the signal
+    pub fn before(&self, signal: &[f32], n: usize) -> Vec<f32> {
+        match self {
+            Padding::Zero => zeroes(n),
+            // Signals that are too short are reflected as far as possible and padded with zeroes
+            Padding::Reflect => (1..=n).rev().map(|i| signal.get(i).copied().unwrap_or(0.)).collect(),
+        }
+    }
 
-        hamming.apply(fft_in, &mut fft_buffer_real);
+    /// The n padding samples after the end of the signal
+    pub fn after(&self, signal: &[f32], n: usize) -> Vec<f32> {
+        match self {
+            Padding::Zero => zeroes(n),
+            Padding::Reflect => (1..=n)
+                .map(|i| signal.len().checked_sub(i + 1).map_or(0., |index| signal[index]))
+                .collect(),
+        }
+    }
+}
+
+/// Window functions that are applied to the frames of the stft
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris,
+    /// Kaiser window; the higher beta, the lower the side lobes and the wider the main lobe
+    Kaiser { beta: f32 },
+}
 
-        let mut fft_buffer_comp: Vec<Complex<f32>> = fft_buffer_real
+impl WindowFunction {
+    /// Parses the name of a window function; the Kaiser window is written as `kaiser` (beta 8.6)
+    /// or e.g. `kaiser-6.5`
+    pub fn from_name(name: &str) -> Option<WindowFunction> {
+        match name {
+            "rectangular" => Some(WindowFunction::Rectangular),
+            "hann" => Some(WindowFunction::Hann),
+            "hamming" => Some(WindowFunction::Hamming),
+            "blackman" => Some(WindowFunction::Blackman),
+            "blackman-harris" => Some(WindowFunction::BlackmanHarris),
+            "kaiser" => Some(WindowFunction::Kaiser { beta: 8.6 }),
+            _ => {
+                let beta = name.strip_prefix("kaiser-")?.parse().ok()?;
+                Some(WindowFunction::Kaiser { beta })
+            }
+        }
+    }
+
+    /// Computes the coefficients of the window function for the given window size
+    pub fn window(&self, size: usize) -> window::Window {
+        let size_f32 = (size.max(2) - 1) as f32;
+        let cosine_sum = |coefficients: &[f32]| window::Window {
+            samples: (0..size)
+                .map(|n| {
+                    coefficients
+                        .iter()
+                        .enumerate()
+                        .map(|(k, a)| (-1f32).powi(k as i32) * a * (2. * PI * k as f32 * n as f32 / size_f32).cos())
+                        .sum()
+                })
+                .collect(),
+        };
+
+        match *self {
+            WindowFunction::Rectangular => window::rectangular(size),
+            WindowFunction::Hann => window::hann(size),
+            WindowFunction::Hamming => window::hamming(size),
+            WindowFunction::Blackman => window::blackman(size),
+            WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168]),
+            WindowFunction::Kaiser { beta } => window::Window {
+                samples: (0..size)
+                    .map(|n| {
+                        let x = 2. * n as f32 / size_f32 - 1.;
+                        bessel_i0(beta * (1. - x * x).max(0.).sqrt()) / bessel_i0(beta)
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// Modified Bessel function of the first kind of order zero (power series)
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let mut k = 1.;
+    while term > sum * 1e-8 {
+        term *= (x / (2. * k)).powi(2);
+        sum += term;
+        k += 1.;
+    }
+    sum
+}
+
+/// Filters the signal with a second-order band-pass filter (biquad with a peak gain of 0 dB)
+/// that passes the frequencies between low and high (in Hz)
+pub fn band_pass(signal: &[f32], sample_rate: u32, low: f32, high: f32) -> Vec<f32> {
+    let centre = (low * high).sqrt();
+    let quality = centre / (high - low);
+    let omega = 2. * PI * centre / sample_rate as f32;
+    let alpha = omega.sin() / (2. * quality);
+
+    // Coefficients divided by a0
+    let a0 = 1. + alpha;
+    let (b0, b2) = (alpha / a0, -alpha / a0);
+    let (a1, a2) = (-2. * omega.cos() / a0, (1. - alpha) / a0);
+
+    let (mut x1, mut x2, mut y1, mut y2) = (0f32, 0f32, 0f32, 0f32);
+    signal
+        .iter()
+        .map(|&x| {
+            let y = b0 * x + b2 * x2 - a1 * y1 - a2 * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            y
+        })
+        .collect()
+}
+
+/// A filterbank that only stores the range of bins each filter has non-zero weights for
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseFilterbank {
+    /// First bin with a non-zero weight and the weights from this bin on, for each filter
+    filters: Vec<(usize, Vec<f32>)>,
+}
+
+impl SparseFilterbank {
+    /// Keeps the non-zero range of each filter of a dense filterbank
+    pub fn from_dense(filterbank: &[Vec<f32>]) -> SparseFilterbank {
+        let filters = filterbank
             .iter()
-            .map(|&value| Complex::new(value, 0f32))
+            .map(|filter| {
+                let start = filter.iter().position(|&weight| weight != 0.).unwrap_or(0);
+                let end = filter.iter().rposition(|&weight| weight != 0.).map_or(start, |last| last + 1);
+                (start, filter[start..end].to_owned())
+            })
             .collect();
-        fft.process(&mut fft_buffer_comp);
-        cur_pos += hop_size;
-        stft.push(fft_buffer_comp);
+        SparseFilterbank { filters }
     }
 
-    // The last fft window may be too short --> zero padding is added before fft computation
-    let mut fft_in: Vec<f32> = signal[cur_pos..signal.len() - 1].to_owned();
-    fft_in.extend(repeat(0f32).take(window_size - fft_in.len()));
-    let mut fft_buffer_real = vec![0f32; window_size];
-    hamming.apply(&fft_in, &mut fft_buffer_real);
+    /// Number of filters
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
 
-    let mut fft_buffer_comp: Vec<Complex<f32>> = fft_buffer_real
-        .iter()
-        .map(|&value| Complex::new(value, 0f32))
-        .collect();
-    fft.process(&mut fft_buffer_comp);
-    stft.push(fft_buffer_comp);
+    /// The bin with the highest weight of a filter
+    pub fn centre(&self, filter: usize) -> usize {
+        let (start, weights) = &self.filters[filter];
+        start + (0..weights.len()).fold(0, |best, i| if weights[i] > weights[best] { i } else { best })
+    }
 
-    WinVec {
-        data: stft,
-        window_size,
-        hop_size,
+    /// Applies all filters to the magnitudes of an FFT frame
+    pub fn apply(&self, magnitudes: &[f32]) -> Vec<f32> {
+        self.filters
+            .iter()
+            .map(|(start, weights)| {
+                magnitudes[*start..]
+                    .iter()
+                    .zip(weights.iter())
+                    .map(|(x, weight)| x * weight)
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// Returns the real-to-complex FFT of the given size. Plans are cached, so they are only created
+/// once and shared by all threads and files.
+pub fn real_fft(size: usize) -> Arc<dyn RealToComplex<f32>> {
+    static PLANNER: OnceLock<Mutex<RealFftPlanner<f32>>> = OnceLock::new();
+    PLANNER
+        .get_or_init(|| Mutex::new(RealFftPlanner::new()))
+        .lock()
+        .expect("planner is not poisoned")
+        .plan_fft_forward(size)
+}
+
+/// Applies the window function and the FFT to single frames of a signal
+pub struct FrameTransform {
+    window_size: usize,
+    window: window::Window,
+    fft: Arc<dyn RealToComplex<f32>>,
+}
+
+impl FrameTransform {
+    pub fn new(window_size: usize, window_function: WindowFunction) -> FrameTransform {
+        FrameTransform {
+            window_size,
+            window: window_function.window(window_size),
+            fft: real_fft(window_size),
+        }
+    }
+
+    /// Computes the FFT (window_size / 2 + 1 bins) of one frame. Frames shorter than the window
+    /// size are zero padded.
+    pub fn transform(&self, frame: &[f32]) -> Vec<Complex<f32>> {
+        let mut fft_in = frame.to_owned();
+        fft_in.resize(self.window_size, 0f32);
+
+        let mut fft_buffer_real = self.fft.make_input_vec();
+        self.window.apply(&fft_in, &mut fft_buffer_real);
+
+        let mut spectrum = self.fft.make_output_vec();
+        self.fft
+            .process(&mut fft_buffer_real, &mut spectrum)
+            .expect("buffers have the length of the plan");
+        spectrum
     }
 }
 
-/// WinVec<A> is a Wrapper over Vec<A> which keeps track of the used windows size and the hop size
-/// That way, we can try easily with different window sizes at the same time
+/// WinVec<A> is a Wrapper over Vec<A> which keeps track of the used windows size, hop size, window
+/// function and sample rate. That way, we can try easily with different window sizes at the same
+/// time, and frames can be converted to times. Element i belongs to the stft frame centred at
+/// sample i * hop_size.
 #[derive(Clone, Debug)]
 pub struct WinVec<A> {
     pub window_size: usize,
     pub hop_size: usize,
+    pub window_function: WindowFunction,
+    pub sample_rate: u32,
     pub data: Vec<A>,
 }
 
 impl<A> WinVec<A> {
+    /// Time (in seconds) of the centre of frame i, relative to the first sample
+    pub fn frame_time(&self, i: usize) -> f64 {
+        (i * self.hop_size) as f64 / self.sample_rate as f64
+    }
+
+    /// Index of the frame whose centre is closest to the given time (in seconds)
+    pub fn frame_at(&self, seconds: f64) -> usize {
+        (seconds.max(0.) * self.sample_rate as f64 / self.hop_size as f64).round() as usize
+    }
+
     // Map the content of WinVec without changing hop_size or window_size
     pub fn map<F, B>(&self, f: F) -> WinVec<B>
         where
@@ -73,15 +310,82 @@ impl<A> WinVec<A> {
             data: f(&self.data),
             window_size: self.window_size,
             hop_size: self.hop_size,
+            window_function: self.window_function,
+            sample_rate: self.sample_rate,
         }
     }
 
     // Set the content of WinVec without changing hop_size or window_size
     pub fn set_data<B>(&self, data: Vec<B>) -> WinVec<B> {
         WinVec {
-            data: data,
+            data,
             window_size: self.window_size,
             hop_size: self.hop_size,
+            window_function: self.window_function,
+            sample_rate: self.sample_rate,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_functions_are_symmetric_and_peak_in_the_middle() {
+        let functions = [
+            WindowFunction::Rectangular,
+            WindowFunction::Hann,
+            WindowFunction::Hamming,
+            WindowFunction::Blackman,
+            WindowFunction::BlackmanHarris,
+            WindowFunction::Kaiser { beta: 8.6 },
+        ];
+        for function in functions {
+            let window = function.window(65);
+            let samples = window.as_slice();
+            for i in 0..samples.len() {
+                assert!((samples[i] - samples[samples.len() - 1 - i]).abs() < 1e-5, "{:?}", function);
+            }
+            assert!((samples[32] - 1.).abs() < 1e-5, "{:?}", function);
+        }
+
+        let blackman_harris = WindowFunction::BlackmanHarris.window(65);
+        assert!(blackman_harris.as_slice()[0] < 1e-4);
+        let kaiser = WindowFunction::Kaiser { beta: 0. }.window(65);
+        assert_eq!(kaiser.as_slice(), WindowFunction::Rectangular.window(65).as_slice());
+        assert_eq!(WindowFunction::from_name("kaiser-6.5"), Some(WindowFunction::Kaiser { beta: 6.5 }));
+    }
+
+    #[test]
+    fn stft_frames_are_centred() {
+        let signal: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.1).sin()).collect();
+        let frames = stft(&signal, 1000, 256, 100, WindowFunction::Hann, Padding::Reflect);
+        assert_eq!(frames.data.len(), 1 + 1000 / 100);
+        assert_eq!(frames.data[0].len(), 256 / 2 + 1);
+        assert_eq!(frames.frame_time(3), 0.3);
+        assert_eq!(frames.frame_at(0.34), 3);
+
+        // Signals shorter than a window still have a frame
+        let short = stft(&signal[..10], 1000, 256, 100, WindowFunction::Hann, Padding::Zero);
+        assert_eq!(short.data.len(), 1);
+    }
+
+    #[test]
+    fn reflect_padding_mirrors_without_repeating_the_edge() {
+        let signal = [1., 2., 3., 4.];
+        assert_eq!(Padding::Reflect.before(&signal, 2), vec![3., 2.]);
+        assert_eq!(Padding::Reflect.after(&signal, 2), vec![3., 2.]);
+        assert_eq!(Padding::Zero.before(&signal, 2), vec![0., 0.]);
+    }
+
+    #[test]
+    fn band_pass_keeps_only_its_band() {
+        let peak = |frequency: f32| {
+            let tone: Vec<f32> = (0..44100).map(|i| (2. * PI * frequency * i as f32 / 44100.).sin()).collect();
+            band_pass(&tone, 44100, 400., 800.)[22050..].iter().fold(0f32, |a, &x| a.max(x.abs()))
+        };
+        assert!((peak(566.) - 1.).abs() < 0.05);
+        assert!(peak(100.) < 0.2 && peak(5000.) < 0.2);
+    }
+}
@@ -0,0 +1,20 @@
+//! The analysis of music not found: decoding, onset detection, tempo estimation and beat tracking.
+//! The command line interface (main.rs) and the benchmarks are built on top of it.
+
+pub mod beat_tracking_and_tempo;
+pub mod constants;
+pub mod decoder;
+pub mod drum_classification;
+pub mod error;
+pub mod f_measure;
+pub mod helpers;
+pub mod offset_detection;
+pub mod onset_algorithms;
+pub mod onset_registry;
+pub mod peak_picking;
+mod plot;
+pub mod post_processing;
+pub mod realtime;
+pub mod streaming;
+pub mod test_signals;
+pub mod track;
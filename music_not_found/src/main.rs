@@ -1,31 +1,30 @@
 extern crate core;
 
-use std::{env, thread};
+use std::{env, process, thread};
 use std::fs::{self};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use ansi_term::Style;
 use clap::{Arg, ArgGroup, ArgMatches, Command, crate_authors, crate_description, crate_version};
-use glob::glob;
+use glob::{glob_with, MatchOptions, Pattern};
 use json::JsonValue;
 
-use beat_tracking_and_tempo::{get_beats, Tempo};
-use beat_tracking_and_tempo::get_tempo;
-use constants::*;
-use f_measure::{f_measure_beats, f_measure_onsets, FMeasure};
-use onset_algorithms::*;
-use peak_picking::{OnsetTimes, PeakPicker};
-use track::Track;
-
-mod beat_tracking_and_tempo;
-mod f_measure;
-mod onset_algorithms;
-mod peak_picking;
-mod plot;
-mod helpers;
-mod track;
-mod constants;
+use music_not_found::beat_tracking_and_tempo::{get_beats, Tempo};
+use music_not_found::beat_tracking_and_tempo::get_tempo;
+use music_not_found::constants::*;
+use music_not_found::error::{self, Error, Result};
+use music_not_found::decoder::SUPPORTED_EXTENSIONS;
+use music_not_found::f_measure::{f_measure_beats, f_measure_onsets, FMeasure};
+use music_not_found::helpers::{Padding, WindowFunction};
+use music_not_found::drum_classification::DrumClassifier;
+use music_not_found::offset_detection::{Note, OffsetDetector};
+use music_not_found::onset_algorithms::*;
+use music_not_found::onset_registry::{COMMON_PARAMETERS, ONSET_ALGORITHMS, OnsetSpec};
+use music_not_found::peak_picking::{Onset, OnsetTimes, TimedPeakPicker};
+use music_not_found::realtime::{Event, RealtimeEngine};
+use music_not_found::streaming::{SampleBlocks, StreamingOnsetDetector};
+use music_not_found::track::{Downmix, Track};
 
 
 /// Main entrance point for CLI Application
@@ -41,6 +40,16 @@ fn main() {
     ]
         .join("");
 
+    let onset_help = format!(
+        "Onset algorithm of the ensemble, given as NAME or NAME:KEY=VALUE,... and repeatable. Parameters of all \
+        algorithms: {}. Algorithms:\n{}\n[default: the two LFSF configurations of the submission]",
+        COMMON_PARAMETERS,
+        ONSET_ALGORITHMS
+            .iter()
+            .map(|(name, description)| format!("  {:<10} {}", name, description))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
     let arg_matches = Command::new(fancy_name)
         .about(crate_description!())
         .author(crate_authors!("\n"))
@@ -50,7 +59,7 @@ fn main() {
                 .short('f')
                 .long("file")
                 .required_unless_present("dir")
-                .help("Process a given .wav file")
+                .help("Process a given audio file (WAV, FLAC, OGG Vorbis, MP3 or AIFF)")
                 .takes_value(true)
                 .value_name("FILE PATH"),
         )
@@ -59,7 +68,7 @@ fn main() {
                 .short('d')
                 .long("directory")
                 .required_unless_present("file")
-                .help("Process all .wav files in the directory")
+                .help("Process all supported audio files in the directory")
                 .takes_value(true)
                 .value_name("DIRECTORY PATH"),
         )
@@ -71,6 +80,102 @@ fn main() {
                 .takes_value(true)
                 .value_name("JSON OUTPUT PATH"),
         )
+        .arg(
+            Arg::new("downmix")
+                .long("downmix")
+                .help("How the channels of a track are combined before detection")
+                .takes_value(true)
+                .possible_values(["mid", "left", "right", "max-energy"])
+                .default_value("mid")
+                .value_name("STRATEGY"),
+        )
+        .arg(
+            Arg::new("per-channel")
+                .long("per-channel")
+                .help("Detect onsets on every channel separately and merge the results"),
+        )
+        .arg(
+            Arg::new("sample-rate")
+                .long("sample-rate")
+                .help("Sample rate (in Hz) tracks are resampled to before analysis [default: 44100]")
+                .takes_value(true)
+                .validator(validate_sample_rate)
+                .value_name("HZ"),
+        )
+        .arg(
+            Arg::new("streaming")
+                .long("streaming")
+                .help("Read and analyse files block by block instead of holding their samples and spectra in memory")
+                .conflicts_with("per-channel"),
+        )
+        .arg(
+            Arg::new("start")
+                .long("start")
+                .help("Only analyse the track from this time on; reported times stay relative to the start of the track")
+                .takes_value(true)
+                .validator(validate_time)
+                .value_name("SECONDS"),
+        )
+        .arg(
+            Arg::new("end")
+                .long("end")
+                .help("Only analyse the track until this time")
+                .takes_value(true)
+                .validator(validate_time)
+                .value_name("SECONDS"),
+        )
+        .arg(
+            Arg::new("padding")
+                .long("padding")
+                .help("How the track is padded at its start and end for the first and last frames")
+                .takes_value(true)
+                .possible_values(["zero", "reflect"])
+                .default_value("zero")
+                .value_name("PADDING"),
+        )
+        .arg(
+            Arg::new("realtime")
+                .long("realtime")
+                .help("Feed files block by block into the real-time engine, which only uses past samples")
+                .conflicts_with_all(&["per-channel", "streaming"]),
+        )
+        .arg(
+            Arg::new("onset")
+                .long("onset")
+                .help("Onset algorithm of the ensemble, e.g. lfsf:win=2048,hop=1024 (repeatable)")
+                .long_help(onset_help.as_str())
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .validator(|spec| OnsetSpec::parse(spec).map(|_| ()).map_err(|e| e.to_string()))
+                .value_name("SPEC")
+                .conflicts_with_all(&["streaming", "realtime", "padding"]),
+        )
+        .arg(
+            Arg::new("needed-score")
+                .long("needed-score")
+                .help("Sum of the scores of the onset algorithms that must agree on an onset for it to count")
+                .takes_value(true)
+                .validator(|score| score.parse::<f64>().map(|_| ()).map_err(|e| e.to_string()))
+                .value_name("SCORE"),
+        )
+        .arg(
+            Arg::new("onset-details")
+                .long("onset-details")
+                .help("Adds the strength, salience and agreement of every onset to the JSON as onset_details")
+                .conflicts_with_all(&["streaming", "realtime"]),
+        )
+        .arg(
+            Arg::new("offsets")
+                .long("offsets")
+                .help("Adds the onset, offset and duration of the note started by every onset to the JSON as notes")
+                .conflicts_with_all(&["streaming", "realtime"]),
+        )
+        .arg(
+            Arg::new("drums")
+                .long("drums")
+                .help("Adds the class of every onset (kick, snare, hi-hat or other) to the JSON as drums")
+                .conflicts_with_all(&["streaming", "realtime"]),
+        )
         .group(
             ArgGroup::new("source")
                 .required(true)
@@ -78,11 +183,26 @@ fn main() {
         )
         .get_matches();
 
+    let options = Options::from_arg_matches(&arg_matches);
+
     if arg_matches.is_present("file") && !arg_matches.is_present("dir") {
-        let output = process_file(Path::new(arg_matches.value_of("file").expect("required")));
-        handle_output(arg_matches, output);
+        let file_path = Path::new(arg_matches.value_of("file").expect("required"));
+        let output = if options.realtime {
+            process_file_realtime(file_path, &options)
+        } else if options.streaming {
+            process_file_streaming(file_path, &options)
+        } else {
+            process_file(file_path, &options)
+        };
+        match output {
+            Ok(output) => handle_output(arg_matches, output),
+            Err(error) => {
+                println!("{} {}", Style::new().bold().paint("Processing failed:"), error);
+                process::exit(1);
+            }
+        }
     } else if arg_matches.is_present("dir") && !arg_matches.is_present("file") {
-        let output = process_folder(Path::new(arg_matches.value_of("dir").expect("required")));
+        let output = process_folder(Path::new(arg_matches.value_of("dir").expect("required")), &options);
         handle_output(arg_matches, output);
     }
 }
@@ -96,7 +216,7 @@ fn handle_output(arg_matches: ArgMatches, output: (Option<FMeasure>, JsonValue))
         println!("Recall:     {}", f_measure.recall);
         println!("F-Measure:  {}", f_measure.f_measure);
     } else {
-        println!("F-Measure was not computed. It may be commented out in code, or due to missing ground truth data or an error occurred during computation.");
+        println!("F-Measure was not computed, as there is no ground truth file next to the track or no onsets were found.");
     }
     if arg_matches.is_present("competition") {
         let path = arg_matches
@@ -109,133 +229,333 @@ fn handle_output(arg_matches: ArgMatches, output: (Option<FMeasure>, JsonValue))
     }
 }
 
-fn process_file(file_path: &Path) -> (Option<FMeasure>, JsonValue) {
-    let track = Track::from_path(file_path);
-
-    let onset_input_big = OnsetInput::from_track(&track, 2048, 1024);
-    let onset_input_small = OnsetInput::from_track(&track, 1024, 441);
-
-
-    /***********************
-     * SPECTRAL DIFFERENCE *
-     ***********************/
-    // As the onset results computed by spectral difference were not good enough,
-    // spectral difference got excluded from our final submission.
-
-    // let spectral_small = SpectralDifference.find_onsets(&onset_input_small);
-    // let spectral_big = SpectralDifference.find_onsets(&onset_input_big);
+/// Options that influence how each file is processed
+#[derive(Clone)]
+struct Options {
+    /// How the channels of a track are reduced to a single signal
+    downmix: Downmix,
+    /// Whether onsets are detected on every channel separately and merged afterwards
+    per_channel: bool,
+    /// The sample rate every track is resampled to before analysis
+    sample_rate: u32,
+    /// Whether files are read and analysed block by block instead of as a whole
+    streaming: bool,
+    /// Whether files are analysed by the real-time engine
+    realtime: bool,
+    /// How the track is padded for the frames at its start and end, unless given by the onset algorithms
+    padding: Padding,
+    /// Time (in seconds) from which on tracks are analysed
+    start: Option<f64>,
+    /// Time (in seconds) until which tracks are analysed
+    end: Option<f64>,
+    /// The onset algorithms whose onsets are combined; the first one is also used for the tempo
+    /// estimation and the beat tracking
+    onsets: Vec<OnsetSpec>,
+    /// Sum of the scores of the onset algorithms needed for an onset of the ensemble
+    needed_score: f64,
+    /// Whether the strength, salience and agreement of every onset are added to the JSON
+    onset_details: bool,
+    /// Whether the offset of the note started by every onset is added to the JSON
+    offsets: bool,
+    /// Whether every onset is classified as kick, snare, hi-hat or other in the JSON
+    drums: bool,
+}
 
-    /********
-     * LFSF *
-     ********/
-    let lfsf_small = LFSF { log_lambda: 0.7 }.find_onsets(&onset_input_small);
-    let lfsf_big = LFSF { log_lambda: 0.7 }.find_onsets(&onset_input_big);
+impl Options {
+    fn from_arg_matches(arg_matches: &ArgMatches) -> Options {
+        let padding = Padding::from_name(arg_matches.value_of("padding").expect("has a default value"))
+            .expect("possible values are checked by clap");
+        Options {
+            downmix: Downmix::from_name(arg_matches.value_of("downmix").expect("has a default value"))
+                .expect("possible values are checked by clap"),
+            per_channel: arg_matches.is_present("per-channel"),
+            sample_rate: arg_matches
+                .value_of("sample-rate")
+                .map_or(ANALYSIS_SAMPLE_RATE, |rate| rate.parse().expect("sample rate is validated by clap")),
+            streaming: arg_matches.is_present("streaming"),
+            realtime: arg_matches.is_present("realtime"),
+            padding,
+            start: arg_matches.value_of("start").map(|time| time.parse().expect("time is validated by clap")),
+            end: arg_matches.value_of("end").map(|time| time.parse().expect("time is validated by clap")),
+            onsets: match arg_matches.values_of("onset") {
+                Some(specs) => specs
+                    .map(|spec| OnsetSpec::parse(spec).expect("onset algorithms are validated by clap"))
+                    .collect(),
+                None => default_onsets(padding),
+            },
+            needed_score: arg_matches
+                .value_of("needed-score")
+                .map_or(ENSEMBLE_NEEDED_SCORE, |score| score.parse().expect("score is validated by clap")),
+            onset_details: arg_matches.is_present("onset-details"),
+            offsets: arg_matches.is_present("offsets"),
+            drums: arg_matches.is_present("drums"),
+        }
+    }
+}
 
-    /******************
-     * HIGH FREQUENCY *
-     ******************/
-    // As the onset results computed by the high frequency method were not good enough,
-    // spectral difference got excluded from our final submission.
+/// Checks that a command line argument is a non-negative time in seconds
+fn validate_time(time: &str) -> std::result::Result<(), String> {
+    match time.parse::<f64>() {
+        Ok(time) if time >= 0. => Ok(()),
+        Ok(_) => Err("time must not be negative".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    // let high_frequency: OnsetOutput = HighFrequencyContent::find_onsets(&onset_input);
+/// Checks that a command line argument is a positive sample rate in Hz
+fn validate_sample_rate(rate: &str) -> std::result::Result<(), String> {
+    match rate.parse::<u32>() {
+        Ok(0) => Err("sample rate must be positive".to_owned()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    /****************
-     * PEAK PICKING *
-     ****************/
+const F_SCORE_LFSF_SMALL: f64 = 0.7216659749653946; // found through the train dataset
+const F_SCORE_LFSF_BIG: f64 = 0.757551539129664; // found through the train dataset
 
-    let peak_picker_small = PeakPicker {
-        local_window_max: 7,
-        local_window_mean: 7, // the higher, the lower the recall but precision slightly increases
-        minimum_distance: 3,
-        delta: 0.1, // must be relatively tiny
+/// The onset algorithms of the submission: LFSF on a small and on a big stft
+fn default_onsets(padding: Padding) -> Vec<OnsetSpec> {
+    let lfsf = |window_size, hop_size, score| OnsetSpec {
+        score,
+        padding,
+        ..OnsetSpec::new(Arc::new(LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() }), window_size, hop_size)
     };
+    vec![lfsf(1024, 441, F_SCORE_LFSF_SMALL), lfsf(2048, 1024, F_SCORE_LFSF_BIG)]
+}
 
-    let peak_picker_big = PeakPicker {
-        local_window_max: 3,
-        local_window_mean: 3, // the higher, the lower the recall but precision slightly increases
-        minimum_distance: 1,
-        delta: 0.1, // must be relatively tiny
+/// Computes the detection functions of all onset algorithms on the downmixed track and combines
+/// their onsets; with per_channel, the onsets are found on each channel separately and merged
+fn detect_onsets(track: &Track, options: &Options) -> Result<(Vec<OnsetOutput>, Vec<Onset>)> {
+    // The detection function of every onset algorithm, on the downmixed track or on a single channel
+    let detect = |channel: Option<usize>| -> Vec<OnsetOutput> {
+        options
+            .onsets
+            .iter()
+            .map(|spec| {
+                let samples = match channel {
+                    Some(channel) => track.channels[channel].to_owned(),
+                    None => track.downmix(options.downmix),
+                };
+                // Time-domain algorithms do not need the stft, so it is only computed for the others
+                let input = if spec.algorithm.needs_stft() {
+                    OnsetInput::from_samples(
+                        samples,
+                        track.sample_rate,
+                        spec.window_size,
+                        spec.hop_size,
+                        spec.window_function,
+                        spec.padding,
+                    )
+                } else {
+                    OnsetInput::without_stft(
+                        samples,
+                        track.sample_rate,
+                        spec.window_size,
+                        spec.hop_size,
+                        spec.window_function,
+                    )
+                };
+                spec.find_onsets(input)
+            })
+            .collect()
     };
 
-    // let f_measure_spectral_small = f_measure_onsets(
-    //     &peak_picker_small.pick(&spectral_small).onset_times(&track).onset_times,
-    //     file_path,
-    // );
-    let _f_score_spectral_small = 0.6785112079439675; // found through the train dataset
-
-    // let f_measure_spectral_big = f_measure_onsets(
-    //     &peak_picker_big.pick(&spectral_big).onset_times(&track).onset_times,
-    //     file_path,
-    // );
-    let _f_score_spectral_big = 0.6935366986327169; // found through the train dataset
-
-    // let f_measure_lfsf_small = f_measure_onsets(
-    //     &peak_picker_small.pick(&lfsf_small).onset_times(&track).onset_times,
-    //     file_path,
-    // );
-    let f_score_lfsf_small = 0.7216659749653946; // found through the train dataset
+    let ensemble = |outputs: &[OnsetOutput]| -> Result<Vec<Onset>> {
+        let mut onsets: Vec<(f64, Vec<Onset>)> = options
+            .onsets
+            .iter()
+            .zip(outputs)
+            .map(|(spec, output)| Ok((spec.score, spec.pick_onsets(output, track)?)))
+            .collect::<Result<_>>()?;
+        // A single algorithm does not need to be confirmed by others
+        if onsets.len() == 1 {
+            return Ok(onsets.remove(0).1);
+        }
+        Ok(combine_detected_onsets(options.needed_score, onsets))
+    };
 
-    // let f_measure_lfsf_big = f_measure_onsets(
-    //     &peak_picker_big.pick(&lfsf_big).onset_times(&track).onset_times,
-    //     file_path,
-    // );
-    let f_score_lfsf_big = 0.757551539129664; // found through the train dataset
+    let outputs = detect(None);
+    let combined_onset = if options.per_channel {
+        // Detect onsets on each channel separately; an onset found on any channel is kept
+        let channel_onsets = (0..track.channel_count())
+            .map(|channel| Ok((1., ensemble(&detect(Some(channel)))?)))
+            .collect::<Result<_>>()?;
+        combine_detected_onsets(0., channel_onsets)
+    } else {
+        ensemble(&outputs)?
+    };
+    Ok((outputs, combined_onset))
+}
 
+fn process_file(file_path: &Path, options: &Options) -> Result<(Option<FMeasure>, JsonValue)> {
+    let track = Track::from_path(file_path)?
+        .resample(options.sample_rate)?
+        .slice(options.start, options.end)?;
 
-    let combined_onset = combine_onsets(
-        ENSEMBLE_NEEDED_SCORE,
-        vec![
-            (
-                f_score_lfsf_small,
-                peak_picker_small
-                    .pick(&lfsf_small)
-                    .onset_times(&track)
-                    .onset_times,
-            ),
-            (
-                f_score_lfsf_big,
-                peak_picker_big
-                    .pick(&lfsf_big)
-                    .onset_times(&track)
-                    .onset_times,
-            ),
-            /* (
-                f_score_spectral_small,
-                peak_picker_small
-                    .pick(&spectral_small)
-                    .onset_times(&track)
-                    .onset_times,
-            ),
-            (
-                f_score_spectral_big,
-                peak_picker_big
-                    .pick(&spectral_big)
-                    .onset_times(&track)
-                    .onset_times,
-            ),*/
-        ],
-    );
+    let (outputs, combined_onset) = detect_onsets(&track, options)?;
 
-    //plot::plot32(&lfsf_small.result.data, "lfsf_small.png");
+    //plot::plot32(&outputs[0].result.data, "onsets.png");
 
     // try to compute beat tracking
-    let tempo = get_tempo(&track, &lfsf_small.result);
+    let tempo = get_tempo(&outputs[0].result)?;
 
     let tempo_for_beats: Tempo;
     if tempo.0.bpm < tempo.1.bpm {
         tempo_for_beats = tempo.0;
     } else { tempo_for_beats = tempo.1 }
-    let beats = get_beats(tempo_for_beats, &peak_picker_small
-        .pick(&lfsf_small)
-        .onset_times(&track)
-        .onset_times, peak_picker_small.pick(&lfsf_small).highest_first_beat_index);
+    let onset_times_first = options.onsets[0].peak_picker.pick(&outputs[0])?.onset_times(&track);
+    let beats = get_beats(tempo_for_beats, &onset_times_first.onset_times, onset_times_first.highest_first_beat)?;
 
     //let beats = get_beats(tempo_for_beats, &combined_onset);
 
-    /**************
-    ** Fill JSON **
-    **************/
+    let onset_times: Vec<f64> = combined_onset.iter().map(|onset| onset.time).collect();
+    let mut file_json = results_to_json(&onset_times, &beats.beats, tempo, track.original_sample_rate);
+    if options.onset_details {
+        file_json["onset_details"] = onset_details_to_json(&combined_onset);
+    }
+    if options.offsets || options.drums {
+        // Offsets and drums are found on the stft of the first onset algorithm
+        let spec = &options.onsets[0];
+        let input = OnsetInput::from_track(
+            &track,
+            options.downmix,
+            spec.window_size,
+            spec.hop_size,
+            spec.window_function,
+            spec.padding,
+        );
+        if options.offsets {
+            let notes = OffsetDetector::default().notes(&input, &combined_onset, track.start_time);
+            file_json["notes"] = notes_to_json(&notes);
+        }
+        if options.drums {
+            let classes = DrumClassifier::default().classify(&input, &combined_onset, track.start_time);
+            file_json["drums"] = classes.iter().map(|class| class.name()).collect::<Vec<_>>().into();
+        }
+    }
+
+    // The onsets are evaluated if a ground truth file lies next to the track; for evaluating the
+    // beats instead, use f_measure_beats(&beats.beats, file_path)
+    Ok((f_measure_onsets(&onset_times, file_path)?, file_json))
+}
+
+/// Processes a file block by block without holding its samples, its stft or its detection functions
+/// in memory; the tempo is estimated while the detection function of the small LFSF is computed. The
+/// results are identical to process_file.
+fn process_file_streaming(file_path: &Path, options: &Options) -> Result<(Option<FMeasure>, JsonValue)> {
+    let mut blocks = SampleBlocks::open(file_path, options.downmix, options.sample_rate)?
+        .range(options.start, options.end)?;
+
+    let mut detector_small = StreamingOnsetDetector::new(
+        LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() },
+        TimedPeakPicker::default().in_frames(options.sample_rate, 441),
+        1024,
+        441,
+        WindowFunction::Hamming,
+        options.sample_rate,
+    )
+        .pad_with(options.padding)
+        .start_at(blocks.start_time())
+        .estimate_tempo();
+    let mut detector_big = StreamingOnsetDetector::new(
+        LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() },
+        TimedPeakPicker::default().in_frames(options.sample_rate, 1024),
+        2048,
+        1024,
+        WindowFunction::Hamming,
+        options.sample_rate,
+    )
+        .pad_with(options.padding)
+        .start_at(blocks.start_time());
+
+    let mut onsets_small = Vec::new();
+    let mut onsets_big = Vec::new();
+    while let Some(block) = blocks.next_block()? {
+        onsets_small.extend(detector_small.push(&block));
+        onsets_big.extend(detector_big.push(&block));
+    }
+    onsets_small.extend(detector_small.finish());
+    onsets_big.extend(detector_big.finish());
+
+    let tempo = detector_small.tempo().expect("is estimated")?;
+    let tempo_for_beats = if tempo.0.bpm < tempo.1.bpm { tempo.0 } else { tempo.1 };
+    let beats = get_beats(
+        tempo_for_beats,
+        &onsets_small,
+        detector_small.highest_first_beat(),
+    )?;
+
+    let combined_onset = combine_onsets(
+        options.needed_score,
+        vec![(F_SCORE_LFSF_SMALL, onsets_small), (F_SCORE_LFSF_BIG, onsets_big)],
+    );
+
+    let file_json = results_to_json(&combined_onset, &beats.beats, tempo, blocks.original_sample_rate());
+    Ok((f_measure_onsets(&combined_onset, file_path)?, file_json))
+}
+
+/// Processes a file like a live stream with the real-time engine; onsets and beats only depend on
+/// past samples. The reported tempo is the one estimated on the end of the track.
+fn process_file_realtime(file_path: &Path, options: &Options) -> Result<(Option<FMeasure>, JsonValue)> {
+    let mut blocks = SampleBlocks::open(file_path, options.downmix, options.sample_rate)?
+        .range(options.start, options.end)?;
+
+    let mut onsets = Vec::new();
+    let mut beats = Vec::new();
+    let mut engine = RealtimeEngine::new(
+        LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() },
+        TimedPeakPicker::default().in_frames(options.sample_rate, 441),
+        1024,
+        441,
+        WindowFunction::Hamming,
+        options.sample_rate,
+        |event| match event {
+            Event::Onset { time } => onsets.push(time),
+            Event::Beat { time, .. } => beats.push(time),
+        },
+    )
+        .pad_with(options.padding)
+        .start_at(blocks.start_time());
+    while let Some(block) = blocks.next_block()? {
+        engine.push(&block);
+    }
+    engine.finish();
+    let tempo = engine.tempo().ok_or(error::Error::TrackTooShort)?;
+
+    let file_json = results_to_json(&onsets, &beats, tempo, blocks.original_sample_rate());
+    Ok((f_measure_onsets(&onsets, file_path)?, file_json))
+}
+
+/// Creates a JSON array with the time, strength, salience and agreement of every onset
+fn onset_details_to_json(onsets: &[Onset]) -> JsonValue {
+    let mut details = json::JsonValue::new_array();
+    for onset in onsets {
+        let _ = details.push(json::object! {
+            time: onset.time,
+            strength: onset.strength,
+            salience: onset.salience,
+            agreement: onset.agreement,
+        });
+    }
+    details
+}
+
+/// Lists the onset, offset and duration (in seconds) of every note
+fn notes_to_json(notes: &[Note]) -> JsonValue {
+    let mut json_notes = json::JsonValue::new_array();
+    for note in notes {
+        let _ = json_notes.push(json::object! {
+            onset: note.onset.time,
+            offset: note.offset,
+            duration: note.duration(),
+        });
+    }
+    json_notes
+}
 
+/// Creates the JSON part of one file, containing its onsets, beats and tempos
+fn results_to_json(onsets: &[f64], beats: &[f64], tempo: (Tempo, Tempo), original_sample_rate: u32) -> JsonValue {
     // Create JSON Part for current file
     let mut file_json = json::JsonValue::new_object();
     file_json["onsets"] = json::JsonValue::new_array();
@@ -244,12 +564,12 @@ fn process_file(file_path: &Path) -> (Option<FMeasure>, JsonValue) {
 
     let onsets_json = &mut file_json["onsets"];
 
-    for onset_time in combined_onset.iter() {
+    for onset_time in onsets.iter() {
         onsets_json.push(onset_time.to_owned()).unwrap();
     }
     let beats_json = &mut file_json["beats"];
 
-    for beat_time in beats.beats.iter() {
+    for beat_time in beats.iter() {
         beats_json.push(beat_time.to_owned()).unwrap();
     }
 
@@ -262,35 +582,52 @@ fn process_file(file_path: &Path) -> (Option<FMeasure>, JsonValue) {
         let _ = file_json["tempo"].push(tempo.0.bpm);
     }
 
-    return (None, file_json);
-    // return (f_measure_onsets(&combined_onset, file_path), file_json);
-    // return (f_measure_beats(&beats.beats, file_path), file_json);
-}
+    // The sample rate of the file, as the analysis may have resampled it
+    file_json["original_sample_rate"] = original_sample_rate.into();
 
-fn process_folder(folder_path: &Path) -> (Option<FMeasure>, json::JsonValue) {
-    let glob_pattern = [folder_path.to_str().unwrap(), "/*.wav"].join("");
+    file_json
+}
 
+fn process_folder(folder_path: &Path, options: &Options) -> (Option<FMeasure>, json::JsonValue) {
     // create empty json file for submission
     let mut overall_json_result = json::JsonValue::new_object();
 
-    let files = glob(&glob_pattern).unwrap();
     let file_count_ref = Arc::new(Mutex::new(0));
     let done_count_ref = Arc::new(Mutex::new(0));
+    // Files (or the folder) that could not be analysed, for the summary at the end
+    let mut failures: Vec<(String, String)> = Vec::new();
 
     /*
-    Create a list of file names to be processed
+    Create a list of the files of all supported formats to be processed; upper case extensions are
+    matched too
      */
-    let mut file_names: Vec<String> = Vec::new();
-
-    files.for_each(|file| {
-        match file {
-            Ok(file_path) => {
-                //       file_names.push(file_path.file_stem().unwrap().to_str().unwrap().to_owned());
-                file_names.push(file_path.as_path().to_str().unwrap().to_owned())
+    let mut file_names: Vec<PathBuf> = Vec::new();
+
+    match folder_path.to_str() {
+        Some(folder) => {
+            for (extension, _) in SUPPORTED_EXTENSIONS {
+                // Special characters of the folder name must not be taken as part of the pattern
+                let glob_pattern = [Pattern::escape(folder).as_str(), "/*.", extension].join("");
+                let options = MatchOptions { case_sensitive: false, ..MatchOptions::new() };
+                match glob_with(&glob_pattern, options) {
+                    Ok(files) => {
+                        for file in files {
+                            match file {
+                                Ok(file_path) => file_names.push(file_path),
+                                Err(e) => failures.push((e.path().display().to_string(), Error::from(e).to_string())),
+                            }
+                        }
+                    }
+                    Err(e) => failures.push((folder_path.display().to_string(), Error::from(e).to_string())),
+                }
             }
-            Err(e) => println!("{:?}", e),
         }
-    });
+        None => failures.push((
+            folder_path.display().to_string(),
+            Error::InvalidOptions("the folder path is not valid UTF-8".to_owned()).to_string(),
+        )),
+    }
+    let listed_count = file_names.len() + failures.len();
 
     /*
     Create threads in chunks through splitting the file names list
@@ -309,17 +646,23 @@ fn process_folder(folder_path: &Path) -> (Option<FMeasure>, json::JsonValue) {
 
         // for each track create a thread
         for file_name in chunk {
+            let file_name_cloned = file_name.clone();
             let file_count_ref_cloned = file_count_ref.clone();
             let mut file_count = file_count_ref_cloned.lock().unwrap();
             *file_count += 1;
 
             let local_state = (file_count_ref.clone(), done_count_ref.clone());
+            let options = options.clone();
             let file_processing = thread::spawn(move || {
-                let file_path = Path::new(&file_name);
+                let file_path = file_name.as_path();
 
-                let name = file_path.file_stem().unwrap().to_str().unwrap().to_owned();
-
-                let output = (name, process_file(file_path));
+                let output = if options.realtime {
+                    process_file_realtime(file_path, &options)
+                } else if options.streaming {
+                    process_file_streaming(file_path, &options)
+                } else {
+                    process_file(file_path, &options)
+                };
 
                 let mut done_count = local_state.1.lock().unwrap();
                 *done_count += 1;
@@ -329,13 +672,35 @@ fn process_folder(folder_path: &Path) -> (Option<FMeasure>, json::JsonValue) {
                 println!("{} of {} done", done_count, *file_count);
                 output
             });
-            file_processings.push(file_processing);
+            file_processings.push((file_name_cloned, file_processing));
         }
-        // join the threads and put results into json
-        for file_processing in file_processings {
-            let (filename, (measure, json_res)) = file_processing.join().unwrap();
-            overall_json_result[filename] = json_res;
-            f_measures.push(measure);
+        // join the threads and put results into json; failed files are collected for the summary
+        for (file_name, file_processing) in file_processings {
+            let display_name = file_name.display().to_string();
+            match file_processing.join() {
+                Ok(Ok((measure, json_res))) => match file_name.file_stem().and_then(|stem| stem.to_str()) {
+                    Some(name) => {
+                        overall_json_result[name] = json_res;
+                        f_measures.push(measure);
+                    }
+                    None => failures.push((
+                        display_name,
+                        Error::InvalidOptions("the file name is not valid UTF-8".to_owned()).to_string(),
+                    )),
+                },
+                Ok(Err(error)) => failures.push((display_name, error.to_string())),
+                Err(_) => failures.push((display_name, "processing panicked".to_owned())),
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!(
+            "{}",
+            Style::new().bold().paint(format!("{} of {} files failed:", failures.len(), listed_count))
+        );
+        for (file_name, error) in failures {
+            println!("{}: {}", file_name, error);
         }
     }
 
@@ -370,3 +735,43 @@ fn process_folder(folder_path: &Path) -> (Option<FMeasure>, json::JsonValue) {
         overall_json_result,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use music_not_found::test_signals::TestSignal;
+
+    fn options(per_channel: bool) -> Options {
+        Options {
+            downmix: Downmix::Mid,
+            per_channel,
+            sample_rate: ANALYSIS_SAMPLE_RATE,
+            streaming: false,
+            realtime: false,
+            padding: Padding::Zero,
+            start: None,
+            end: None,
+            onsets: default_onsets(Padding::Zero),
+            needed_score: ENSEMBLE_NEEDED_SCORE,
+            onset_details: false,
+            offsets: false,
+            drums: false,
+        }
+    }
+
+    #[test]
+    fn per_channel_finds_onsets_the_downmix_cancels() {
+        // The right channel is the inverted left one, so their mid is silent
+        let (mut track, truth) = TestSignal::click_track(120., 6.).generate();
+        let inverted = track.channels[0].iter().map(|x| -x).collect();
+        track.channels.push(inverted);
+
+        let (_, downmixed) = detect_onsets(&track, &options(false)).unwrap();
+        assert!(downmixed.is_empty());
+
+        let (_, per_channel) = detect_onsets(&track, &options(true)).unwrap();
+        let times: Vec<f64> = per_channel.iter().map(|onset| onset.time).collect();
+        let result = music_not_found::f_measure::f_measure(&times, &truth.onsets, ONSET_ACCURACY);
+        assert_eq!(result.f_measure, 1., "{:?}", times);
+    }
+}
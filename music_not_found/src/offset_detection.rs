@@ -0,0 +1,181 @@
+use crate::onset_algorithms::{BandGroup, OnsetInput};
+use crate::peak_picking::Onset;
+
+/// An onset together with the offset of the note it starts
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Note {
+    pub onset: Onset,
+    /// Time (in seconds) at which the note has decayed
+    pub offset: f64,
+}
+
+impl Note {
+    /// Length of the note (in seconds)
+    pub fn duration(&self) -> f64 {
+        self.offset - self.onset.time
+    }
+}
+
+/// Finds the end of the note started by each onset. The energy of each band group is tracked in
+/// the stft; after an onset, every group that takes part in the note reaches its peak within the
+/// attack time, and the note ends once the energy of all these groups has decayed below the
+/// threshold relative to their peaks. A note ends at the latest with the next onset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OffsetDetector {
+    /// Band groups whose energies are tracked separately
+    pub groups: Vec<BandGroup>,
+    /// Decay (in dB) below the peak energy of a group after which the group is silent. Groups whose
+    /// peak is more than this below the loudest group do not take part in the note.
+    pub threshold: f32,
+    /// Time (in seconds) after an onset within which the energy of a group reaches its peak
+    pub attack_time: f64,
+}
+
+impl Default for OffsetDetector {
+    fn default() -> OffsetDetector {
+        OffsetDetector {
+            groups: BandGroup::low_mid_high(),
+            threshold: 20.,
+            attack_time: 0.05,
+        }
+    }
+}
+
+impl OffsetDetector {
+    /// Finds the offsets of onsets (sorted by time) that may have been found on other stfts, e.g.
+    /// combined by `combine_detected_onsets`. Onset times are relative to the start of the file,
+    /// which is at start_time of the input.
+    pub fn notes(&self, input: &OnsetInput, onsets: &[Onset], start_time: f64) -> Vec<Note> {
+        let frames = &input.stft;
+        let onset_frames: Vec<usize> = onsets.iter().map(|onset| frames.frame_at(onset.time - start_time)).collect();
+        let offset_frames = self.offset_frames(input, &onset_frames);
+        onsets
+            .iter()
+            .zip(offset_frames)
+            .enumerate()
+            .map(|(n, (&onset, offset))| {
+                let next_onset = onsets.get(n + 1).map_or(f64::INFINITY, |next| next.time);
+                let offset = (start_time + frames.frame_time(offset)).clamp(onset.time, next_onset.max(onset.time));
+                Note { onset, offset }
+            })
+            .collect()
+    }
+
+    /// The offset frame of every onset frame (sorted)
+    pub fn offset_frames(&self, input: &OnsetInput, onset_frames: &[usize]) -> Vec<usize> {
+        let energies = self.band_energies(input);
+        let last_frame = input.stft.data.len().saturating_sub(1);
+        let attack_frames =
+            (self.attack_time * input.sampling_rate as f64 / input.stft.hop_size as f64).round() as usize;
+        let ratio = 10f32.powf(-self.threshold / 10.);
+
+        onset_frames
+            .iter()
+            .enumerate()
+            .map(|(n, &onset)| {
+                let onset = onset.min(last_frame);
+                let end = onset_frames.get(n + 1).map_or(last_frame, |&next| next.clamp(onset, last_frame));
+                let attack_end = (onset + attack_frames).min(end);
+
+                // Frame and energy of the peak of each group after the onset
+                let peaks: Vec<(usize, f32)> = energies
+                    .iter()
+                    .map(|energy| {
+                        (onset..=attack_end)
+                            .map(|i| (i, energy[i]))
+                            .fold((onset, f32::MIN), |peak, value| if value.1 > peak.1 { value } else { peak })
+                    })
+                    .collect();
+                let loudest = peaks.iter().map(|&(_, peak)| peak).fold(0., f32::max);
+
+                energies
+                    .iter()
+                    .zip(peaks)
+                    .filter(|&(_, (_, peak))| peak > 0. && peak >= ratio * loudest)
+                    .map(|(energy, (peak_frame, peak))| {
+                        (peak_frame + 1..=end)
+                            .find(|&i| energy[i] < ratio * peak)
+                            .unwrap_or(end)
+                    })
+                    .max()
+                    .unwrap_or(onset)
+            })
+            .collect()
+    }
+
+    /// Energy of each band group in every frame of the stft
+    pub fn band_energies(&self, input: &OnsetInput) -> Vec<Vec<f32>> {
+        let bin_width = input.sampling_rate as f32 / input.stft.window_size as f32;
+        self.groups
+            .iter()
+            .map(|group| {
+                input
+                    .stft
+                    .data
+                    .iter()
+                    .map(|frame| {
+                        frame
+                            .iter()
+                            .enumerate()
+                            .filter(|&(bin, _)| group.contains(bin as f32 * bin_width))
+                            .map(|(_, x)| x.norm_sqr())
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::ONSET_ACCURACY;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::onset_algorithms::{OnsetAlgorithm, LFSF};
+    use crate::test_signals::{Sound, TestSignal, PICKER};
+    use crate::track::Downmix;
+
+    #[test]
+    fn notes_end_when_tones_end() {
+        let lengths = [0.1, 0.35, 0.2, 0.45];
+        let (track, truth) = TestSignal {
+            sounds: lengths
+                .iter()
+                .zip([440., 110., 880., 6000.])
+                .map(|(&length, frequency)| Sound::Tone { frequency, length })
+                .collect(),
+            ..TestSignal::click_track(120., 8.)
+        }
+            .generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let peaks = PICKER.pick(&LFSF::default().find_onsets(&input)).unwrap();
+
+        let onsets = peaks.onset_times(&track).onsets;
+        let notes = OffsetDetector::default().notes(&input, &onsets, track.start_time);
+        assert_eq!(notes.len(), truth.onsets.len());
+        for (note, (&onset, sound)) in notes.iter().zip(truth.onsets.iter().zip(&truth.sounds)) {
+            let Sound::Tone { length, .. } = *sound else { unreachable!() };
+            assert!((note.onset.time - onset).abs() < ONSET_ACCURACY);
+            assert!((note.offset - (onset + length as f64)).abs() < ONSET_ACCURACY, "{:?} {}", note, length);
+        }
+    }
+
+    #[test]
+    fn notes_end_at_the_latest_with_the_next_onset() {
+        // The tones are longer than the time between two beats
+        let (track, truth) = TestSignal::tone_bursts(400., 4., &[440., 660.]).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let onsets: Vec<Onset> = truth
+            .onsets
+            .iter()
+            .map(|&time| Onset { time, strength: 1., salience: 1., agreement: 1 })
+            .collect();
+
+        let notes = OffsetDetector::default().notes(&input, &onsets, track.start_time);
+        assert_eq!(notes.len(), onsets.len());
+        for (note, next) in notes.iter().zip(&notes[1..]) {
+            assert!(note.offset <= next.onset.time && next.onset.time - note.offset < 1e-6, "{:?} {:?}", note, next);
+        }
+    }
+}
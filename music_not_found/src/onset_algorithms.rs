@@ -1,11 +1,15 @@
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex, OnceLock};
 
 use rustfft::num_complex::Complex;
 use rustfft::num_traits::abs;
 
 use crate::constants::*;
-use crate::helpers::{stft, WinVec, zeroes};
-use crate::track::Track;
+use crate::helpers::{band_pass, stft, Padding, SparseFilterbank, WinVec, WindowFunction, zeroes};
+use crate::peak_picking::{Onset, TimedPeakPicker};
+use crate::track::{Downmix, Track};
 
 /// Data structure holding the samples of a track and its STFT
 pub struct OnsetInput {
@@ -15,26 +19,189 @@ pub struct OnsetInput {
 }
 
 impl OnsetInput {
-    /// Extract samples from a track and compute its stft using the given window- and hop-size.
-    pub fn from_track(track: &Track, window_size: usize, hop_size: usize) -> OnsetInput {
+    /// Reduce the channels of a track to a single signal using the given downmix strategy and
+    /// compute its stft using the given window function, window- and hop-size and padding.
+    pub fn from_track(
+        track: &Track,
+        downmix: Downmix,
+        window_size: usize,
+        hop_size: usize,
+        window_function: WindowFunction,
+        padding: Padding,
+    ) -> OnsetInput {
+        let samples = track.downmix(downmix);
+        OnsetInput::from_samples(samples, track.sample_rate, window_size, hop_size, window_function, padding)
+    }
+
+    /// Compute the stft of mono samples using the given window function, window- and hop-size and
+    /// padding.
+    pub fn from_samples(
+        samples: Vec<f32>,
+        sampling_rate: u32,
+        window_size: usize,
+        hop_size: usize,
+        window_function: WindowFunction,
+        padding: Padding,
+    ) -> OnsetInput {
+        let stft = stft(&samples, sampling_rate, window_size, hop_size, window_function, padding);
+        OnsetInput {
+            samples,
+            sampling_rate,
+            stft,
+        }
+    }
+
+    /// Keeps mono samples without computing their stft, for the time-domain algorithms only (see
+    /// `OnsetAlgorithm::needs_stft`). The stft holds no frames, but window function, window- and
+    /// hop-size for framing the samples.
+    pub fn without_stft(
+        samples: Vec<f32>,
+        sampling_rate: u32,
+        window_size: usize,
+        hop_size: usize,
+        window_function: WindowFunction,
+    ) -> OnsetInput {
+        let stft = WinVec {
+            window_size,
+            hop_size,
+            window_function,
+            sample_rate: sampling_rate,
+            data: Vec::new(),
+        };
         OnsetInput {
-            samples: track.samples.to_owned(),
-            sampling_rate: track.header.sample_rate.to_owned(),
-            stft: stft(&track.samples, window_size, hop_size),
+            samples,
+            sampling_rate,
+            stft,
         }
     }
+
+    /// Replaces the stft by its adaptively whitened version, see `AdaptiveWhitening`
+    pub fn whiten(mut self, whitening: &AdaptiveWhitening) -> OnsetInput {
+        self.stft = whitening.apply(&self.stft);
+        self
+    }
 }
 
 /// Data structure holding the values determined by the onset detection function (not holding onsets!)
 pub struct OnsetOutput {
     pub result: WinVec<f32>,
+    /// Detection functions of single frequency bands, for algorithms that keep them (otherwise
+    /// empty); their sum is the result
+    pub bands: Vec<BandOutput>,
+}
+
+/// A range of frequencies whose detection function is kept separately
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandGroup {
+    pub name: String,
+    /// Frequency range (in Hz); bands whose centre lies in [min_frequency, max_frequency) belong to
+    /// the group
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+}
+
+impl BandGroup {
+    pub fn new(name: &str, min_frequency: f32, max_frequency: f32) -> BandGroup {
+        BandGroup {
+            name: name.to_owned(),
+            min_frequency,
+            max_frequency,
+        }
+    }
+
+    /// Low (bass drum, bass), mid (snare, voices) and high (hi-hats, cymbals) frequencies
+    pub fn low_mid_high() -> Vec<BandGroup> {
+        vec![
+            BandGroup::new("low", 0., BAND_LOW_MID_FREQUENCY),
+            BandGroup::new("mid", BAND_LOW_MID_FREQUENCY, BAND_MID_HIGH_FREQUENCY),
+            BandGroup::new("high", BAND_MID_HIGH_FREQUENCY, f32::INFINITY),
+        ]
+    }
+
+    pub fn contains(&self, frequency: f32) -> bool {
+        self.min_frequency <= frequency && frequency < self.max_frequency
+    }
+}
+
+/// The detection function of a band group
+pub struct BandOutput {
+    pub group: BandGroup,
+    pub result: WinVec<f32>,
+}
+
+impl OnsetOutput {
+    /// The detection function of a single band group as output of its own, e.g. for peak picking
+    /// or tempo estimation
+    pub fn band(&self, index: usize) -> OnsetOutput {
+        OnsetOutput {
+            result: self.bands[index].result.clone(),
+            bands: Vec::new(),
+        }
+    }
 }
 
 /// Defines an interface for the onset algorithms
 pub trait OnsetAlgorithm {
     fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput;
+
+    /// Whether the algorithm reads the stft of its input; time-domain algorithms only need the
+    /// samples, so their input can be created with `OnsetInput::without_stft`
+    fn needs_stft(&self) -> bool {
+        true
+    }
+}
+
+
+/*************
+ * WHITENING *
+ *************/
+
+/// Adaptive whitening (Stowell and Plumbley, 2007): every bin is divided by the recent peak of its
+/// magnitude, so that loud sustained partials do not cover the onsets of quiet notes. The peak
+/// memory decays by 60 dB within the relaxation time, so quiet passages after loud ones are
+/// normalised again soon.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveWhitening {
+    /// Time (in seconds) in which a peak decays by 60 dB
+    pub relaxation_time: f32,
+    /// Smallest peak a bin is divided by, so that (almost) silent bins are not amplified to full
+    /// scale
+    pub floor: f32,
+}
+
+impl Default for AdaptiveWhitening {
+    fn default() -> AdaptiveWhitening {
+        AdaptiveWhitening {
+            relaxation_time: 1.,
+            floor: 1e-3,
+        }
+    }
 }
 
+impl AdaptiveWhitening {
+    /// Whitens all frames of an stft; the phases stay the same
+    pub fn apply(&self, stft: &WinVec<Vec<Complex<f32>>>) -> WinVec<Vec<Complex<f32>>> {
+        let frames_per_second = stft.sample_rate as f32 / stft.hop_size as f32;
+        let memory = 10f32.powf(-3. / (self.relaxation_time * frames_per_second));
+
+        let mut peaks = vec![self.floor; stft.window_size / 2 + 1];
+        let data = stft
+            .data
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .zip(peaks.iter_mut())
+                    .map(|(x, peak)| {
+                        *peak = x.norm().max(self.floor).max(memory * *peak);
+                        x / *peak
+                    })
+                    .collect()
+            })
+            .collect();
+        stft.set_data(data)
+    }
+}
 
 /******************
  * HIGH FREQUENCY *
@@ -52,7 +219,7 @@ impl HighFrequencyContent {
 
 impl OnsetAlgorithm for HighFrequencyContent {
     fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
-        let weights = HighFrequencyContent::weights(input.stft.window_size);
+        let weights = HighFrequencyContent::weights(input.stft.window_size / 2 + 1);
         let data: WinVec<f32> = input.stft.map(|data| {
             data.iter()
                 .map(|single_fft| {
@@ -66,7 +233,7 @@ impl OnsetAlgorithm for HighFrequencyContent {
                 })
                 .collect()
         });
-        OnsetOutput { result: data }
+        OnsetOutput { result: data, bands: Vec::new() }
     }
 }
 
@@ -81,7 +248,7 @@ impl SpectralDifference {}
 impl OnsetAlgorithm for SpectralDifference {
     fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
         let mut spectral_differences: Vec<Vec<f32>> = Vec::new();
-        let empty_diff = vec![0; 1024].iter().map(|&i| (i as f32) * 0.0).collect();
+        let empty_diff = zeroes(input.stft.window_size / 2 + 1);
         spectral_differences.push(empty_diff);
         let data = &input.stft.data;
 
@@ -103,6 +270,7 @@ impl OnsetAlgorithm for SpectralDifference {
 
         OnsetOutput {
             result: input.stft.set_data(data),
+            bands: Vec::new(),
         }
     }
 }
@@ -111,80 +279,633 @@ impl OnsetAlgorithm for SpectralDifference {
  * LFSF *
  ********/
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct LFSF {
     pub log_lambda: f32,
+    /// Number of mel bands
+    pub mel_bands: usize,
+    /// Frequency range (in Hz) of the mel filterbank; None means 0 Hz and the Nyquist frequency
+    pub min_frequency: Option<f32>,
+    pub max_frequency: Option<f32>,
+    /// Number of frames between a frame and the frame it is compared with (at least 1)
+    pub lag: usize,
+}
+
+impl Default for LFSF {
+    fn default() -> LFSF {
+        LFSF {
+            log_lambda: 1.,
+            mel_bands: MEL_BANDS,
+            min_frequency: None,
+            max_frequency: None,
+            lag: 1,
+        }
+    }
+}
+
+/// Identifies a filterbank in the cache of `cached_filterbank`; frequencies are stored as bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum FilterbankKey {
+    Mel {
+        sampling_rate: u32,
+        fft_size: usize,
+        bands: usize,
+        min_frequency: Option<u32>,
+        max_frequency: Option<u32>,
+    },
+    Log {
+        sampling_rate: u32,
+        fft_size: usize,
+        bands_per_octave: usize,
+        min_frequency: u32,
+        max_frequency: u32,
+    },
+}
+
+/// Returns the filterbank of the key, which is only created once and shared by all threads and
+/// files
+fn cached_filterbank(key: FilterbankKey, create: impl FnOnce() -> Vec<Vec<f32>>) -> Arc<SparseFilterbank> {
+    static FILTERBANKS: OnceLock<Mutex<HashMap<FilterbankKey, Arc<SparseFilterbank>>>> = OnceLock::new();
+
+    FILTERBANKS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .expect("filterbank cache is not poisoned")
+        .entry(key)
+        .or_insert_with(|| Arc::new(SparseFilterbank::from_dense(&create())))
+        .clone()
 }
 
 impl LFSF {
-    fn apply_filterbank(sampling_rate: u32, data: &[Vec<Complex<f32>>]) -> Vec<Vec<f32>> {
-        let filterbank: Vec<Vec<f32>> = mel_filter::mel(
-            sampling_rate as usize,
-            data[0].len(),
-            Some(MEL_BANDS),
-            None,
-            None,
-            false,
-            mel_filter::NormalizationFactor::One,
-        );
+    /// Returns the mel filterbank for FFT frames of the given size. Filterbanks are cached, so each
+    /// one is only created once and shared by all threads and files.
+    pub fn filterbank(&self, sampling_rate: u32, fft_size: usize) -> Arc<SparseFilterbank> {
+        let key = FilterbankKey::Mel {
+            sampling_rate,
+            fft_size,
+            bands: self.mel_bands,
+            min_frequency: self.min_frequency.map(f32::to_bits),
+            max_frequency: self.max_frequency.map(f32::to_bits),
+        };
+        cached_filterbank(key, || {
+            mel_filterbank(sampling_rate, fft_size, self.mel_bands, self.min_frequency, self.max_frequency)
+        })
+    }
 
-        data.iter()
-            .map(|frame| {
-                filterbank
-                    .iter()
-                    .map(|mel_frame| {
-                        frame
-                            .iter()
-                            .enumerate()
-                            .map(|(i, x)| x.norm() * mel_frame[i / 2])
-                            .sum()
-                    })
-                    .collect()
-            })
+    /// Applies the mel filterbank and the logarithmic compression to a single FFT frame
+    pub fn log_mel_frame(&self, filterbank: &SparseFilterbank, frame: &[Complex<f32>]) -> Vec<f32> {
+        let magnitudes: Vec<f32> = frame.iter().map(|x| x.norm()).collect();
+        filterbank
+            .apply(&magnitudes)
+            .into_iter()
+            .map(|x| (x * self.log_lambda + 1.).log10())
             .collect()
     }
 
-    fn apply_log(&self, data: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
-        data.into_iter()
-            .map(|frame| {
-                frame
-                    .into_iter()
-                    .map(|x| (x * self.log_lambda + 1.).log10())
-                    .collect()
+    /// Computes the LFSF detection function value of a log-mel frame (see slide 61 L04.pdf)
+    pub fn flux(current: &[f32], previous: &[f32]) -> f32 {
+        let half_wave = |x| f32::max(x, 0.);
+        current
+            .iter()
+            .zip(previous.iter())
+            .map(|(x, p)| half_wave(x - p))
+            .sum()
+    }
+}
+
+impl LFSF {
+    /// Like find_onsets, but also keeps the flux of each band group separately. Each mel band
+    /// belongs to the first group that contains its centre frequency; bands in no group only count
+    /// for the sum.
+    pub fn find_band_onsets(&self, input: &OnsetInput, groups: &[BandGroup]) -> OnsetOutput {
+        let filterbank = self.filterbank(input.sampling_rate, input.stft.window_size);
+        let bin_frequency = input.sampling_rate as f32 / input.stft.window_size as f32;
+        let group_of: Vec<Option<usize>> = (0..filterbank.len())
+            .map(|band| {
+                let centre = filterbank.centre(band) as f32 * bin_frequency;
+                groups.iter().position(|group| group.contains(centre))
             })
-            .collect()
+            .collect();
+
+        let data: Vec<Vec<f32>> = input
+            .stft
+            .data
+            .iter()
+            .map(|frame| self.log_mel_frame(&filterbank, frame))
+            .collect();
+        let zero_vector = zeroes(filterbank.len());
+
+        let mut detection_vector = zeroes(data.len());
+        let mut band_vectors = vec![zeroes(data.len()); groups.len()];
+        for i in 0..data.len() {
+            let previous = if i >= self.lag { &data[i - self.lag] } else { &zero_vector };
+            for (band, (x, p)) in data[i].iter().zip(previous.iter()).enumerate() {
+                let flux = f32::max(x - p, 0.);
+                detection_vector[i] += flux;
+                if let Some(group) = group_of[band] {
+                    band_vectors[group][i] += flux;
+                }
+            }
+        }
+
+        OnsetOutput {
+            result: input.stft.set_data(detection_vector),
+            bands: groups
+                .iter()
+                .zip(band_vectors)
+                .map(|(group, data)| BandOutput {
+                    group: group.clone(),
+                    result: input.stft.set_data(data),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// LFSF split into band groups (see `LFSF::find_band_onsets`), either to detect onsets in a single
+/// group or to keep the detection functions of all groups besides their sum
+pub struct BandLFSF {
+    pub lfsf: LFSF,
+    pub groups: Vec<BandGroup>,
+    /// Index of the group whose detection function is the result; None keeps the sum
+    pub selected: Option<usize>,
+}
+
+impl OnsetAlgorithm for BandLFSF {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        let output = self.lfsf.find_band_onsets(input, &self.groups);
+        match self.selected {
+            Some(index) => output.band(index),
+            None => output,
+        }
     }
 }
 
 impl OnsetAlgorithm for LFSF {
     fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
         let raw_data = &input.stft.data;
+        let filterbank = self.filterbank(input.sampling_rate, input.stft.window_size);
 
-        let data = &self.apply_log(LFSF::apply_filterbank(input.sampling_rate, &raw_data[..]));
+        let data: Vec<Vec<f32>> = raw_data
+            .iter()
+            .map(|frame| self.log_mel_frame(&filterbank, frame))
+            .collect();
 
-        let mut detection_vector: Vec<f32> = zeroes(data.len());
         let zero_vector = zeroes(data[0].len());
 
-        let half_wave = |x| f32::max(x, 0.);
         let previous = |i| {
-            if i == 0 {
+            if i < self.lag {
                 &zero_vector
             } else {
-                &data[i - 1]
+                &data[i - self.lag]
             }
         };
 
-        for i in 0..data.len() {
-            // This compoutes the LFSF detection function (see slide 61 L04.pdf)
-            detection_vector[i] = data[i]
+        let detection_vector: Vec<f32> = (0..data.len())
+            .map(|i| LFSF::flux(&data[i], previous(i)))
+            .collect();
+
+        OnsetOutput {
+            result: input.stft.set_data(detection_vector),
+            bands: Vec::new(),
+        }
+    }
+}
+
+
+/*************
+ * SUPERFLUX *
+ *************/
+
+/// Spectral flux on a logarithmically filtered spectrogram, where each frame is compared with a
+/// maximum filtered frame some frames before it (Böck and Widmer, 2013). Partials that slightly
+/// move in frequency, like in a vibrato, are covered by the maximum filter and do not cause false
+/// positives.
+pub struct SuperFlux {
+    pub log_lambda: f32,
+    /// Number of filters per octave of the logarithmic filterbank
+    pub bands_per_octave: usize,
+    /// Frequency range (in Hz) covered by the filterbank
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+    /// Number of neighbouring bands the maximum filter spans (including the band itself)
+    pub max_filter_width: usize,
+    /// Number of frames between a frame and the frame it is compared with
+    pub lag: usize,
+}
+
+impl Default for SuperFlux {
+    /// The parameters of the paper, for window size 2048 and hop size 220 (200 frames per second)
+    fn default() -> SuperFlux {
+        SuperFlux {
+            log_lambda: 1.,
+            bands_per_octave: 24,
+            min_frequency: 30.,
+            max_frequency: 17000.,
+            max_filter_width: 3,
+            lag: 2,
+        }
+    }
+}
+
+impl SuperFlux {
+    /// The lag of the paper: the compared frames are (about) a quarter of a window apart, where a
+    /// Hann window has fallen to half of its maximum
+    pub fn lag_for(window_size: usize, hop_size: usize) -> usize {
+        max(1, (window_size as f32 / 4. / hop_size as f32).round() as usize)
+    }
+
+    /// Peak picker with the parameters of the paper: maxima within 30 ms, mean of 100 ms, at least
+    /// 30 ms between onsets
+    pub fn peak_picker() -> TimedPeakPicker {
+        TimedPeakPicker {
+            local_window_max: 30.,
+            local_window_mean: 100.,
+            delta: 1.1,
+            minimum_distance: 30.,
+        }
+    }
+
+    /// Returns the logarithmic filterbank for FFT frames of the given size; it is cached like the
+    /// one of LFSF
+    pub fn filterbank(&self, sampling_rate: u32, fft_size: usize) -> Arc<SparseFilterbank> {
+        let key = FilterbankKey::Log {
+            sampling_rate,
+            fft_size,
+            bands_per_octave: self.bands_per_octave,
+            min_frequency: self.min_frequency.to_bits(),
+            max_frequency: self.max_frequency.to_bits(),
+        };
+        cached_filterbank(key, || {
+            log_filterbank(sampling_rate, fft_size, self.bands_per_octave, self.min_frequency, self.max_frequency)
+        })
+    }
+
+    /// Applies the filterbank and the logarithmic compression to a single FFT frame
+    pub fn log_filtered_frame(&self, filterbank: &SparseFilterbank, frame: &[Complex<f32>]) -> Vec<f32> {
+        let magnitudes: Vec<f32> = frame.iter().map(|x| x.norm()).collect();
+        filterbank
+            .apply(&magnitudes)
+            .into_iter()
+            .map(|x| (x * self.log_lambda + 1.).log10())
+            .collect()
+    }
+
+    /// Maximum of each band and its neighbours (in frequency direction)
+    pub fn max_filter(&self, frame: &[f32]) -> Vec<f32> {
+        let reach = self.max_filter_width / 2;
+        (0..frame.len())
+            .map(|band| {
+                let neighbours = &frame[band.saturating_sub(reach)..min(frame.len(), band + reach + 1)];
+                neighbours.iter().cloned().fold(f32::MIN, f32::max)
+            })
+            .collect()
+    }
+}
+
+impl OnsetAlgorithm for SuperFlux {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        let filterbank = self.filterbank(input.sampling_rate, input.stft.window_size);
+        let data: Vec<Vec<f32>> = input
+            .stft
+            .data
+            .iter()
+            .map(|frame| self.log_filtered_frame(&filterbank, frame))
+            .collect();
+        let filtered: Vec<Vec<f32>> = data.iter().map(|frame| self.max_filter(frame)).collect();
+
+        let zero_vector = zeroes(filterbank.len());
+        let detection_vector: Vec<f32> = (0..data.len())
+            .map(|i| {
+                let reference = if i >= self.lag { &filtered[i - self.lag] } else { &zero_vector };
+                LFSF::flux(&data[i], reference)
+            })
+            .collect();
+
+        OnsetOutput {
+            result: input.stft.set_data(detection_vector),
+            bands: Vec::new(),
+        }
+    }
+}
+
+
+/*****************
+ * SPECTRAL FLUX *
+ *****************/
+
+/// How the bins of an FFT frame are grouped before frames are compared
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Binning {
+    /// Every bin on its own
+    Linear,
+    /// A mel filterbank with the given number of bands
+    Mel { bands: usize },
+    /// Triangular filters with logarithmically spaced centre frequencies (in Hz)
+    LogFrequency { bands_per_octave: usize, min_frequency: f32, max_frequency: f32 },
+}
+
+/// How the binned magnitudes are compressed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Magnitude {
+    Linear,
+    /// log10(lambda * x + 1), like at LFSF
+    Log { lambda: f32 },
+}
+
+/// How a frame is compared with an earlier one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Distance {
+    /// Sum of the increases of all bands
+    L1,
+    /// Sum of the squared increases of all bands
+    L2,
+    /// Modified Kullback-Leibler distance: sum of log(1 + x / (p + epsilon)) of all bands, which
+    /// emphasises bands that rise from (almost) silence
+    ModifiedKullbackLeibler,
+}
+
+/// The family of spectral flux functions: each frame is binned and compressed, and compared with
+/// the frame `lag` frames before it (zero frames before the start). LFSF is the L1 distance of log
+/// magnitudes of MEL_BANDS mel bands with lag 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpectralFlux {
+    pub binning: Binning,
+    pub magnitude: Magnitude,
+    pub distance: Distance,
+    pub lag: usize,
+}
+
+impl SpectralFlux {
+    /// The filterbank of the binning; None for linear binning. Filterbanks are cached like the
+    /// ones of LFSF, with which mel filterbanks are shared.
+    pub fn filterbank(&self, sampling_rate: u32, fft_size: usize) -> Option<Arc<SparseFilterbank>> {
+        match self.binning {
+            Binning::Linear => None,
+            Binning::Mel { bands } => {
+                let lfsf = LFSF { mel_bands: bands, ..LFSF::default() };
+                Some(lfsf.filterbank(sampling_rate, fft_size))
+            }
+            Binning::LogFrequency { bands_per_octave, min_frequency, max_frequency } => {
+                let key = FilterbankKey::Log {
+                    sampling_rate,
+                    fft_size,
+                    bands_per_octave,
+                    min_frequency: min_frequency.to_bits(),
+                    max_frequency: max_frequency.to_bits(),
+                };
+                Some(cached_filterbank(key, || {
+                    log_filterbank(sampling_rate, fft_size, bands_per_octave, min_frequency, max_frequency)
+                }))
+            }
+        }
+    }
+
+    /// Bins and compresses a single FFT frame
+    pub fn frame(&self, filterbank: Option<&SparseFilterbank>, frame: &[Complex<f32>]) -> Vec<f32> {
+        let magnitudes: Vec<f32> = frame.iter().map(|x| x.norm()).collect();
+        let binned = match filterbank {
+            None => magnitudes,
+            Some(filterbank) => filterbank.apply(&magnitudes),
+        };
+        match self.magnitude {
+            Magnitude::Linear => binned,
+            Magnitude::Log { lambda } => binned.into_iter().map(|x| (x * lambda + 1.).log10()).collect(),
+        }
+    }
+
+    /// Distance of a binned frame to the earlier one it is compared with
+    pub fn distance(&self, current: &[f32], previous: &[f32]) -> f32 {
+        let pairs = current.iter().zip(previous.iter());
+        match self.distance {
+            Distance::L1 => pairs.map(|(x, p)| f32::max(x - p, 0.)).sum(),
+            Distance::L2 => pairs.map(|(x, p)| f32::max(x - p, 0.).powi(2)).sum(),
+            Distance::ModifiedKullbackLeibler => pairs.map(|(x, p)| (1. + x / (p + MKL_EPSILON)).ln()).sum(),
+        }
+    }
+}
+
+impl OnsetAlgorithm for SpectralFlux {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        let filterbank = self.filterbank(input.sampling_rate, input.stft.window_size);
+        let data: Vec<Vec<f32>> = input
+            .stft
+            .data
+            .iter()
+            .map(|frame| self.frame(filterbank.as_deref(), frame))
+            .collect();
+
+        let zero_vector = zeroes(data[0].len());
+        let detection_vector: Vec<f32> = (0..data.len())
+            .map(|i| {
+                let previous = if i >= self.lag { &data[i - self.lag] } else { &zero_vector };
+                self.distance(&data[i], previous)
+            })
+            .collect();
+
+        OnsetOutput {
+            result: input.stft.set_data(detection_vector),
+            bands: Vec::new(),
+        }
+    }
+}
+
+
+/*******************
+ * ENERGY ENVELOPE *
+ *******************/
+
+/// How the energy of a frame is turned into an envelope
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Envelope {
+    /// Root mean square of the samples
+    Rms,
+    /// Logarithm of the mean energy; its differences are relative changes, so quiet onsets count
+    /// as much as loud ones
+    LogEnergy,
+}
+
+/// Half-wave rectified derivative of the energy envelope, computed in the time domain without
+/// the stft. Frames are centred at i * hop_size like the stft frames (zero padded) and weighted
+/// with the window function of the stft. With bands, the signal is split by band-pass filters
+/// and the derivatives of all bands are summed up.
+pub struct EnergyEnvelope {
+    pub envelope: Envelope,
+    /// Lower and upper edge (in Hz) of each band; no bands means the whole signal
+    pub bands: Vec<(f32, f32)>,
+}
+
+impl EnergyEnvelope {
+    /// The given number of adjacent octave bands, starting at the given frequency (in Hz)
+    pub fn octave_bands(lowest: f32, count: usize) -> Vec<(f32, f32)> {
+        (0..count)
+            .map(|i| (lowest * 2f32.powi(i as i32), lowest * 2f32.powi(i as i32 + 1)))
+            .collect()
+    }
+
+    /// The envelope value of each frame of the signal
+    pub fn envelope(&self, signal: &[f32], frames: &WinVec<Vec<Complex<f32>>>) -> Vec<f32> {
+        let window_size = frames.window_size;
+        let mut weights = zeroes(window_size);
+        frames.window_function.window(window_size).apply(&vec![1.; window_size], &mut weights);
+        let weight_sum: f32 = weights.iter().sum();
+
+        (0..=signal.len() / frames.hop_size)
+            .map(|i| {
+                // Frame i starts half a window before sample i * hop_size
+                let start = (i * frames.hop_size) as isize - (window_size / 2) as isize;
+                let energy = weights
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(n, weight)| {
+                        let index = usize::try_from(start + n as isize).ok()?;
+                        signal.get(index).map(|x| weight * x * x)
+                    })
+                    .sum::<f32>()
+                    / weight_sum;
+                match self.envelope {
+                    Envelope::Rms => energy.sqrt(),
+                    Envelope::LogEnergy => (energy + LOG_ENERGY_FLOOR).log10(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl OnsetAlgorithm for EnergyEnvelope {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        let signals = if self.bands.is_empty() {
+            vec![input.samples.clone()]
+        } else {
+            self.bands
                 .iter()
-                .enumerate()
-                .map(|(j, x)| half_wave(x - previous(i)[j]))
-                .sum();
+                .map(|&(low, high)| band_pass(&input.samples, input.sampling_rate, low, high))
+                .collect()
+        };
+
+        let mut detection_vector = zeroes(input.samples.len() / input.stft.hop_size + 1);
+        for signal in signals {
+            let envelope = self.envelope(&signal, &input.stft);
+            for i in 1..envelope.len() {
+                detection_vector[i] += f32::max(envelope[i] - envelope[i - 1], 0.);
+            }
         }
+
         OnsetOutput {
             result: input.stft.set_data(detection_vector),
+            bands: Vec::new(),
         }
     }
+
+    fn needs_stft(&self) -> bool {
+        false
+    }
+}
+
+
+/******************
+ * COMPLEX DOMAIN *
+ ******************/
+
+/// Compares each frame with a prediction from the two previous frames: the magnitude stays the
+/// same and the phase advances by the same amount as before. Catches soft, tonal onsets that
+/// magnitude-only functions miss.
+pub struct ComplexDomain {
+    /// Only counts bins whose magnitude increases (rectified complex domain), so that offsets
+    /// are ignored
+    pub rectified: bool,
+}
+
+impl ComplexDomain {
+    /// Distance of a frame to its prediction from the two previous frames
+    pub fn deviation(
+        &self,
+        current: &[Complex<f32>],
+        previous: &[Complex<f32>],
+        before_previous: &[Complex<f32>],
+    ) -> f32 {
+        current
+            .iter()
+            .zip(previous.iter().zip(before_previous.iter()))
+            .filter(|(x, (p, _))| !self.rectified || x.norm() >= p.norm())
+            .map(|(x, (p, b))| {
+                let predicted_phase = 2. * p.arg() - b.arg();
+                (x - Complex::from_polar(p.norm(), predicted_phase)).norm()
+            })
+            .sum()
+    }
+}
+
+impl OnsetAlgorithm for ComplexDomain {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        with_previous_frames(input, |current, previous, before_previous| {
+            self.deviation(current, previous, before_previous)
+        })
+    }
+}
+
+
+/*******************
+ * PHASE DEVIATION *
+ *******************/
+
+/// Mean absolute deviation of the phase of each bin from a steady advance (the second difference
+/// of the phase). Fires on pitch changes, even if the amplitude stays the same.
+pub struct PhaseDeviation;
+
+/// Like PhaseDeviation, but each bin is weighted by its magnitude, so that the random phase of
+/// silent bins does not matter
+pub struct WeightedPhaseDeviation;
+
+/// Like WeightedPhaseDeviation, but divided by the sum of the magnitudes, so that the result does
+/// not depend on the loudness
+pub struct NormalizedWeightedPhaseDeviation;
+
+/// Maps a phase to the range [-pi, pi)
+pub fn princarg(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(2. * PI) - PI
+}
+
+/// The magnitude of each bin of the current frame together with the absolute deviation of its phase
+fn phase_deviations<'a>(
+    current: &'a [Complex<f32>],
+    previous: &'a [Complex<f32>],
+    before_previous: &'a [Complex<f32>],
+) -> impl Iterator<Item = (f32, f32)> + 'a {
+    current
+        .iter()
+        .zip(previous.iter().zip(before_previous.iter()))
+        .map(|(x, (p, b))| (x.norm(), princarg(x.arg() - 2. * p.arg() + b.arg()).abs()))
+}
+
+impl OnsetAlgorithm for PhaseDeviation {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        with_previous_frames(input, |current, previous, before_previous| {
+            let deviations = phase_deviations(current, previous, before_previous);
+            deviations.map(|(_, deviation)| deviation).sum::<f32>() / current.len() as f32
+        })
+    }
+}
+
+impl OnsetAlgorithm for WeightedPhaseDeviation {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        with_previous_frames(input, |current, previous, before_previous| {
+            let deviations = phase_deviations(current, previous, before_previous);
+            deviations.map(|(magnitude, deviation)| magnitude * deviation).sum::<f32>() / current.len() as f32
+        })
+    }
+}
+
+impl OnsetAlgorithm for NormalizedWeightedPhaseDeviation {
+    fn find_onsets(&self, input: &OnsetInput) -> OnsetOutput {
+        with_previous_frames(input, |current, previous, before_previous| {
+            let (weighted, magnitudes) = phase_deviations(current, previous, before_previous)
+                .fold((0., 0.), |(weighted, magnitudes), (magnitude, deviation)| {
+                    (weighted + magnitude * deviation, magnitudes + magnitude)
+                });
+            if magnitudes > 0. { weighted / magnitudes } else { 0. }
+        })
+    }
 }
 
 
@@ -192,20 +913,119 @@ impl OnsetAlgorithm for LFSF {
  * HELPERS *
  ***********/
 
+/// Creates a mel filterbank with the given number of bands and frequency range (in Hz; None means
+/// 0 Hz and the Nyquist frequency) for FFT frames of the given size; each filter has a weight for
+/// each of the fft_size / 2 + 1 bins
+pub fn mel_filterbank(
+    sampling_rate: u32,
+    fft_size: usize,
+    bands: usize,
+    min_frequency: Option<f32>,
+    max_frequency: Option<f32>,
+) -> Vec<Vec<f32>> {
+    mel_filter::mel(
+        sampling_rate as usize,
+        fft_size,
+        Some(bands),
+        min_frequency,
+        max_frequency,
+        false,
+        mel_filter::NormalizationFactor::One,
+    )
+}
+
+/// Creates triangular filters with logarithmically spaced centre frequencies (in Hz) for FFT
+/// frames of the given size; each filter has a weight for each of the fft_size / 2 + 1 bins and
+/// the weights of a filter sum up to 1. Centres that fall into the same bin are merged.
+pub fn log_filterbank(
+    sampling_rate: u32,
+    fft_size: usize,
+    bands_per_octave: usize,
+    min_frequency: f32,
+    max_frequency: f32,
+) -> Vec<Vec<f32>> {
+    let bins = fft_size / 2 + 1;
+    let bin_of = |frequency: f32| (frequency * fft_size as f32 / sampling_rate as f32).round() as usize;
+    let max_frequency = max_frequency.min(sampling_rate as f32 / 2.);
+
+    let mut centres: Vec<usize> = (0..)
+        .map(|i| min_frequency * 2f32.powf(i as f32 / bands_per_octave as f32))
+        .take_while(|&frequency| frequency <= max_frequency)
+        .map(|frequency| bin_of(frequency).min(bins - 1))
+        .collect();
+    centres.dedup();
+
+    centres
+        .windows(3)
+        .map(|edges| {
+            let (start, centre, stop) = (edges[0], edges[1], edges[2]);
+            let mut filter = zeroes(bins);
+            for (offset, weight) in filter[start..centre].iter_mut().enumerate() {
+                *weight = offset as f32 / (centre - start) as f32;
+            }
+            for (offset, weight) in filter[centre..stop].iter_mut().enumerate() {
+                *weight = (stop - centre - offset) as f32 / (stop - centre) as f32;
+            }
+            let sum: f32 = filter.iter().sum();
+            filter.iter_mut().for_each(|weight| *weight /= sum);
+            filter
+        })
+        .collect()
+}
+
+/// Applies a function to each frame and its two previous frames (zero frames before the start)
+fn with_previous_frames(
+    input: &OnsetInput,
+    f: impl Fn(&[Complex<f32>], &[Complex<f32>], &[Complex<f32>]) -> f32,
+) -> OnsetOutput {
+    let data = &input.stft.data;
+    let zero_frame = vec![Complex::new(0., 0.); data[0].len()];
+    let frame = |i: usize, back: usize| if i >= back { &data[i - back] } else { &zero_frame };
+
+    let detection_vector: Vec<f32> = (0..data.len())
+        .map(|i| f(frame(i, 0), frame(i, 1), frame(i, 2)))
+        .collect();
+
+    OnsetOutput {
+        result: input.stft.set_data(detection_vector),
+        bands: Vec::new(),
+    }
+}
+
 /// Combines onset times (after peak picking) from different algorithms. Using the needed_score,
 /// someone can determine how much of the passed onset results need an onset to have found in
 /// order to count it as one.
 pub fn combine_onsets(needed_score: f64, onsets: Vec<(f64, Vec<f64>)>) -> Vec<f64> {
+    let onsets = onsets
+        .into_iter()
+        .map(|(score, times)| {
+            let onsets = times
+                .into_iter()
+                .map(|time| Onset { time, strength: 0., salience: 0., agreement: 1 })
+                .collect();
+            (score, onsets)
+        })
+        .collect();
+    combine_detected_onsets(needed_score, onsets)
+        .into_iter()
+        .map(|onset| onset.time)
+        .collect()
+}
+
+/// Like combine_onsets, but keeps the strength of the onsets: a combined onset has the strength and
+/// salience of its most salient member onset, and its agreement is the sum of the agreements of
+/// the results that found it (each result counted once), i.e. the number of onset algorithms.
+pub fn combine_detected_onsets(needed_score: f64, onsets: Vec<(f64, Vec<Onset>)>) -> Vec<Onset> {
     let mut combined_values = Vec::new();
 
-    for (score, vec) in onsets {
+    for (member, (score, vec)) in onsets.into_iter().enumerate() {
         for x in vec {
-            combined_values.push((x, score));
+            combined_values.push((x, score, member));
         }
     }
 
-    combined_values.sort_by(|(a, _), (b, _)| {
-        if b > a {
+    combined_values.sort_by(|(a, _, _), (b, _, _)| {
+        if b.time > a.time {
             Ordering::Less
         } else {
             Ordering::Greater
@@ -218,24 +1038,432 @@ pub fn combine_onsets(needed_score: f64, onsets: Vec<(f64, Vec<f64>)>) -> Vec<f6
     let mut i = 0;
 
     while i < combined_values.len() {
-        let (t, _) = combined_values[i];
-        if t < time {
+        let (onset, _, _) = combined_values[i];
+        if onset.time < time {
             i += 1;
             continue;
         }
 
-        time = t;
+        time = onset.time;
 
         let mut scores = Vec::new();
-        while i < combined_values.len() && combined_values[i].0 - time <= ONSET_ACCURACY {
-            scores.push(combined_values[i].1);
+        let mut members: Vec<usize> = Vec::new();
+        let mut agreement = 0;
+        let mut most_salient = onset;
+        while i < combined_values.len() && combined_values[i].0.time - time <= ONSET_ACCURACY {
+            let (onset, score, member) = combined_values[i];
+            scores.push(score);
+            if !members.contains(&member) {
+                members.push(member);
+                agreement += onset.agreement;
+            }
+            if onset.salience > most_salient.salience {
+                most_salient = onset;
+            }
             i += 1;
         }
 
         if scores.into_iter().sum::<f64>() > needed_score {
-            combined.push(time);
+            combined.push(Onset { time, agreement, ..most_salient });
         }
     }
 
     combined
 }
+
+/// Merges the onsets found in different band groups: an onset found in any of them counts, onsets
+/// of different groups closer than ONSET_ACCURACY count once. All groups belong to the same
+/// algorithm, so the agreement stays 1.
+pub fn merge_band_onsets(band_onsets: Vec<Vec<Onset>>) -> Vec<Onset> {
+    combine_detected_onsets(0., band_onsets.into_iter().map(|onsets| (1., onsets)).collect())
+        .into_iter()
+        .map(|onset| Onset { agreement: 1, ..onset })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::f_measure::f_measure;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::peak_picking::PeakPicker;
+    use crate::post_processing::{post_process, PostProcessing};
+    use crate::test_signals::{frequency_steps, Sound, TestSignal, PICKER};
+
+    fn input(track: &Track) -> OnsetInput {
+        OnsetInput::from_track(track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero)
+    }
+
+    /// Onset times found by the algorithm; the detection function is normalised to a maximum of 1,
+    /// so that the same peak picker can be used for all algorithms
+    fn onsets(algorithm: &dyn OnsetAlgorithm, track: &Track) -> Vec<f64> {
+        let output = post_process(algorithm.find_onsets(&input(track)), &[PostProcessing::NormalizeMax]);
+        PICKER.pick(&output).unwrap().onset_times(track).onset_times
+    }
+
+    #[test]
+    fn complex_domain_finds_tone_onsets() {
+        let (track, truth) = TestSignal::tone_bursts(100., 10., &[440., 660., 880.]).generate();
+
+        // The end of a tone deviates from the prediction as well, but only the rectified variant
+        // ignores it
+        let found = onsets(&ComplexDomain { rectified: false }, &track);
+        assert!(f_measure(&found, &truth.onsets, ONSET_ACCURACY).recall > 0.95);
+        let found = onsets(&ComplexDomain { rectified: true }, &track);
+        let result = f_measure(&found, &truth.onsets, ONSET_ACCURACY);
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+    }
+
+    #[test]
+    fn complex_domain_is_small_for_stationary_tones() {
+        let samples: Vec<f32> = (0..44100)
+            .map(|i| (2. * std::f32::consts::PI * 440. * i as f32 / 44100.).sin())
+            .collect();
+        let input = OnsetInput::from_samples(samples, 44100, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let output = ComplexDomain { rectified: false }.find_onsets(&input);
+
+        // Apart from the edges, the phase advances exactly as predicted
+        let middle = &output.result.data[10..output.result.data.len() - 10];
+        let start = output.result.data[1];
+        assert!(middle.iter().all(|&x| x < 0.05 * start), "{:?}", middle);
+    }
+
+    #[test]
+    fn superflux_ignores_vibrato() {
+        let (track, truth) = TestSignal::vibrato_notes(50., 10., &[440., 330., 523.25]).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 2048, 220, WindowFunction::Hann, Padding::Zero);
+
+        // Both detection functions are normalised and picked with the same peak picker, so only
+        // the maximum filter makes the difference
+        let picker = TimedPeakPicker { delta: 0.05, ..SuperFlux::peak_picker() };
+        let result = |algorithm: &dyn OnsetAlgorithm| {
+            let mut output = algorithm.find_onsets(&input);
+            let max = output.result.data.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+            output.result.data.iter_mut().for_each(|x| *x /= max);
+            let found = picker.pick(&output).unwrap().onset_times(&track).onset_times;
+            f_measure(&found, &truth.onsets, ONSET_ACCURACY)
+        };
+
+        let superflux = result(&SuperFlux::default());
+        let lfsf = result(&LFSF { log_lambda: 1., ..LFSF::default() });
+        assert!(superflux.f_measure > 0.9, "{}", superflux.f_measure);
+        assert!(lfsf.precision < 0.5 * superflux.precision, "{} {}", lfsf.precision, superflux.precision);
+    }
+
+    #[test]
+    fn superflux_with_its_peak_picker_finds_drum_onsets() {
+        let (track, truth) = TestSignal::drum_pattern(110., 10.).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 2048, 220, WindowFunction::Hann, Padding::Zero);
+        let output = SuperFlux::default().find_onsets(&input);
+        let found = SuperFlux::peak_picker().pick(&output).unwrap().onset_times(&track).onset_times;
+        let result = f_measure(&found, &truth.onsets, ONSET_ACCURACY);
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+        assert_eq!(SuperFlux::lag_for(2048, 220), SuperFlux::default().lag);
+    }
+
+    #[test]
+    fn superflux_max_filter_spreads_to_neighbours() {
+        let superflux = SuperFlux::default();
+        assert_eq!(superflux.max_filter(&[0., 0., 1., 0., 3.]), vec![0., 1., 1., 3., 3.]);
+
+        // Each filter of the filterbank sums up to 1
+        let filterbank = superflux.filterbank(44100, 2048);
+        assert!(filterbank.apply(&[1.; 1025]).iter().all(|sum| (sum - 1.).abs() < 1e-4));
+
+        // The filterbank is cached and shared with the spectral flux of the same binning
+        let flux = SpectralFlux {
+            binning: Binning::LogFrequency { bands_per_octave: 24, min_frequency: 30., max_frequency: 17000. },
+            magnitude: Magnitude::Linear,
+            distance: Distance::L1,
+            lag: 1,
+        };
+        assert!(Arc::ptr_eq(&filterbank, &flux.filterbank(44100, 2048).unwrap()));
+    }
+
+    #[test]
+    fn energy_envelopes_find_drum_onsets() {
+        let (track, truth) = TestSignal::drum_pattern(110., 10.).generate();
+        let f_measure_of = |algorithm: EnergyEnvelope| {
+            f_measure(&onsets(&algorithm, &track), &truth.onsets, ONSET_ACCURACY)
+        };
+
+        for bands in [Vec::new(), EnergyEnvelope::octave_bands(50., 8)] {
+            let result = f_measure_of(EnergyEnvelope { envelope: Envelope::LogEnergy, bands });
+            assert!(result.f_measure > 0.9, "{}", result.f_measure);
+        }
+        let result = f_measure_of(EnergyEnvelope { envelope: Envelope::Rms, bands: Vec::new() });
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+
+        // Summed up RMS derivatives are dominated by the loud low bands, so the quiet hi-hats are
+        // missed, while relative changes of the log energy count the same in every band
+        let result = f_measure_of(EnergyEnvelope { envelope: Envelope::Rms, bands: EnergyEnvelope::octave_bands(50., 8) });
+        assert!(result.precision > 0.9 && result.recall < 0.75, "{} {}", result.precision, result.recall);
+    }
+
+    #[test]
+    fn energy_envelope_does_not_need_the_stft() {
+        let (track, _) = TestSignal::click_track(120., 3.).generate();
+        let algorithm = EnergyEnvelope { envelope: Envelope::LogEnergy, bands: Vec::new() };
+        let with_stft = algorithm.find_onsets(&input(&track));
+        let samples = track.downmix(Downmix::Mid);
+        let without_stft = algorithm.find_onsets(&OnsetInput::without_stft(
+            samples,
+            44100,
+            1024,
+            441,
+            WindowFunction::Hann,
+        ));
+
+        assert!(!algorithm.needs_stft());
+        assert_eq!(with_stft.result.data, without_stft.result.data);
+    }
+
+    #[test]
+    fn whitening_finds_onsets_after_loud_passages() {
+        let (mut track, truth) = TestSignal::drum_pattern(110., 10.).generate();
+        // The second half is 40 dB quieter
+        track.channels[0][5 * 44100..].iter_mut().for_each(|x| *x *= 0.01);
+
+        let result = |input: OnsetInput| {
+            let mut output = SpectralDifference.find_onsets(&input);
+            let max = output.result.data.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+            output.result.data.iter_mut().for_each(|x| *x /= max);
+            let found = PICKER.pick(&output).unwrap().onset_times(&track).onset_times;
+            f_measure(&found, &truth.onsets, ONSET_ACCURACY)
+        };
+        let plain = result(input(&track));
+        let whitened = result(input(&track).whiten(&AdaptiveWhitening::default()));
+        assert!(plain.recall < 0.5, "{}", plain.recall);
+        assert!(whitened.f_measure > 0.9, "{}", whitened.f_measure);
+    }
+
+    #[test]
+    fn whitening_normalises_stationary_tones() {
+        let samples: Vec<f32> = (0..44100)
+            .map(|i| 0.01 * (2. * std::f32::consts::PI * 440. * i as f32 / 44100.).sin())
+            .collect();
+        let input = OnsetInput::from_samples(samples, 44100, 1024, 441, WindowFunction::Hann, Padding::Zero);
+        let whitened = input.whiten(&AdaptiveWhitening::default());
+
+        // The strongest bin of each frame is its own peak
+        let frame = &whitened.stft.data[50];
+        let strongest = frame.iter().map(|x| x.norm()).fold(0., f32::max);
+        assert!((strongest - 1.).abs() < 1e-5, "{}", strongest);
+    }
+
+    #[test]
+    fn band_groups_separate_kicks_from_hi_hats() {
+        let (track, truth) = TestSignal::drum_pattern(110., 10.).generate();
+        let input = input(&track);
+        let lfsf = LFSF { log_lambda: 0.7, ..LFSF::default() };
+        let output = lfsf.find_band_onsets(&input, &BandGroup::low_mid_high());
+
+        // The bands add up to the usual detection function
+        let sum = lfsf.find_onsets(&input).result.data;
+        for (i, value) in sum.iter().enumerate() {
+            let bands: f32 = output.bands.iter().map(|band| band.result.data[i]).sum();
+            assert!((bands - value).abs() <= 1e-3 * value.max(1.), "{} {}", bands, value);
+        }
+
+        let band_onsets: Vec<Vec<Onset>> = PICKER
+            .pick_bands(&output)
+            .unwrap()
+            .iter()
+            .map(|peaks| peaks.onset_times(&track).onsets)
+            .collect();
+        let times = |onsets: &[Onset]| -> Vec<f64> { onsets.iter().map(|onset| onset.time).collect() };
+        let onsets_of = |sounds: &[Sound]| -> Vec<f64> {
+            truth
+                .onsets
+                .iter()
+                .zip(truth.sounds.iter())
+                .filter(|(_, sound)| sounds.contains(sound))
+                .map(|(&onset, _)| onset)
+                .collect()
+        };
+
+        // The noise of the snare reaches into the low band, but much weaker than the kick
+        let low_picker = PeakPicker { delta: 0.5, ..PICKER };
+        let low_onsets = low_picker.pick(&output.band(0)).unwrap().onset_times(&track).onset_times;
+        let low = f_measure(&low_onsets, &onsets_of(&[Sound::Kick]), ONSET_ACCURACY);
+        assert!(low.f_measure > 0.9, "{}", low.f_measure);
+        let high = f_measure(&times(&band_onsets[2]), &onsets_of(&[Sound::HiHat, Sound::Snare]), ONSET_ACCURACY);
+        assert!(high.f_measure > 0.9, "{}", high.f_measure);
+
+        let merged = merge_band_onsets(band_onsets);
+        assert!(merged.iter().all(|onset| onset.agreement == 1));
+        let result = f_measure(&times(&merged), &truth.onsets, ONSET_ACCURACY);
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+
+        // Selecting a group makes its detection function the result
+        let low_lfsf = BandLFSF { lfsf, groups: BandGroup::low_mid_high(), selected: Some(0) };
+        let low_output = low_lfsf.find_onsets(&input);
+        assert_eq!(low_output.result.data, output.bands[0].result.data);
+        assert!(low_output.bands.is_empty());
+    }
+
+    #[test]
+    fn lfsf_filterbank_is_cached_and_sparse() {
+        let lfsf = LFSF { min_frequency: Some(100.), max_frequency: Some(8000.), mel_bands: 40, ..LFSF::default() };
+        let filterbank = lfsf.filterbank(44100, 1024);
+        assert!(Arc::ptr_eq(&filterbank, &lfsf.filterbank(44100, 1024)));
+        assert!(!Arc::ptr_eq(&filterbank, &LFSF::default().filterbank(44100, 1024)));
+        assert_eq!(filterbank.len(), 40);
+
+        // Applying the sparse filterbank is the same as multiplying with the dense one
+        let dense = mel_filterbank(44100, 1024, 40, Some(100.), Some(8000.));
+        let magnitudes: Vec<f32> = (0..513).map(|bin| (bin % 7) as f32).collect();
+        for (sparse, filter) in filterbank.apply(&magnitudes).iter().zip(dense.iter()) {
+            let expected: f32 = magnitudes.iter().zip(filter.iter()).map(|(x, weight)| x * weight).sum();
+            assert!((sparse - expected).abs() < 1e-4, "{} {}", sparse, expected);
+        }
+
+        // All centres lie within the frequency range
+        let bin_frequency = 44100. / 1024.;
+        assert!((0..40).all(|band| {
+            let centre = filterbank.centre(band) as f32 * bin_frequency;
+            (100. ..=8000.).contains(&centre)
+        }));
+    }
+
+    #[test]
+    fn spectral_flux_with_lfsf_parameters_is_lfsf() {
+        let (track, _) = TestSignal::drum_pattern(110., 5.).generate();
+        let input = input(&track);
+        let flux = SpectralFlux {
+            binning: Binning::Mel { bands: MEL_BANDS },
+            magnitude: Magnitude::Log { lambda: 0.7 },
+            distance: Distance::L1,
+            lag: 1,
+        };
+        assert_eq!(flux.find_onsets(&input).result.data, LFSF { log_lambda: 0.7, ..LFSF::default() }.find_onsets(&input).result.data);
+        // Both share the same cached mel filterbank
+        assert!(Arc::ptr_eq(&flux.filterbank(44100, 1024).unwrap(), &LFSF::default().filterbank(44100, 1024)));
+    }
+
+    #[test]
+    fn spectral_flux_family_finds_tone_onsets() {
+        let (track, truth) = TestSignal::tone_bursts(100., 6., &[440., 660., 880.]).generate();
+        let binnings = [
+            Binning::Linear,
+            Binning::Mel { bands: MEL_BANDS },
+            Binning::LogFrequency { bands_per_octave: 12, min_frequency: 30., max_frequency: 17000. },
+        ];
+        for binning in binnings {
+            for magnitude in [Magnitude::Linear, Magnitude::Log { lambda: 1. }] {
+                for distance in [Distance::L1, Distance::L2, Distance::ModifiedKullbackLeibler] {
+                    let flux = SpectralFlux { binning, magnitude, distance, lag: 2 };
+                    let result = f_measure(&onsets(&flux, &track), &truth.onsets, ONSET_ACCURACY);
+                    assert!(result.f_measure > 0.9, "{:?}: {}", flux, result.f_measure);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modified_kullback_leibler_finds_quiet_drum_onsets() {
+        let (track, truth) = TestSignal::drum_pattern(110., 6.).generate();
+        let flux = |distance| SpectralFlux {
+            binning: Binning::Mel { bands: MEL_BANDS },
+            magnitude: Magnitude::Linear,
+            distance,
+            lag: 1,
+        };
+
+        // The ratio of the magnitudes does not depend on the loudness, so the quiet hi-hats are
+        // found next to the kicks, unlike with the squared differences
+        let result = f_measure(&onsets(&flux(Distance::ModifiedKullbackLeibler), &track), &truth.onsets, ONSET_ACCURACY);
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+        let result = f_measure(&onsets(&flux(Distance::L2), &track), &truth.onsets, ONSET_ACCURACY);
+        assert!(result.recall < 0.75, "{}", result.recall);
+    }
+
+    /// The highest detection function value around the given onset and a value 0.3 s before it
+    fn change_and_steady(algorithm: &dyn OnsetAlgorithm, track: &Track, onset: f64) -> (f32, f32) {
+        let output = algorithm.find_onsets(&input(track));
+        let change = output.result.frame_at(onset);
+        let at_change = output.result.data[change - 1..=change + 1].iter().cloned().fold(0., f32::max);
+        (at_change, output.result.data[output.result.frame_at(onset - 0.3)])
+    }
+
+    #[test]
+    fn princarg_wraps_phases() {
+        assert!((princarg(3. * PI) + PI).abs() < 1e-5);
+        assert!((princarg(-0.5) + 0.5).abs() < 1e-6);
+        assert!((princarg(2. * PI + 0.25) - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn phase_deviations_fire_on_pitch_changes_without_amplitude_change() {
+        let (track, truth) = frequency_steps(100., 10., &[440., 523.25, 659.25, 587.33]);
+        let algorithms: [(&str, &dyn OnsetAlgorithm); 2] = [
+            ("weighted phase deviation", &WeightedPhaseDeviation),
+            ("normalized weighted phase deviation", &NormalizedWeightedPhaseDeviation),
+        ];
+        for (name, algorithm) in algorithms {
+            let found = onsets(algorithm, &track);
+            let result = f_measure(&found, &truth.onsets, ONSET_ACCURACY);
+            assert!(result.f_measure > 0.9, "{}: {}", name, result.f_measure);
+
+            // Between two changes, the phase advances steadily
+            let (at_change, steady) = change_and_steady(algorithm, &track, truth.onsets[3]);
+            assert!(at_change > 100. * steady, "{}: {} {}", name, at_change, steady);
+        }
+
+        // Without weighting, the random phases of (almost) silent bins add a lot of noise, but
+        // all changes are still found
+        let found = onsets(&PhaseDeviation, &track);
+        assert!(f_measure(&found, &truth.onsets, ONSET_ACCURACY).recall > 0.95);
+        let (at_change, steady) = change_and_steady(&PhaseDeviation, &track, truth.onsets[3]);
+        assert!(at_change > 2. * steady, "{} {}", at_change, steady);
+    }
+
+    #[test]
+    fn combined_onsets_keep_strength_and_agreement() {
+        let onset = |time, salience| Onset { time, strength: 10. * salience, salience, agreement: 1 };
+        let combined = combine_detected_onsets(
+            0.5,
+            vec![
+                (1., vec![onset(1., 0.2), onset(2., 0.9)]),
+                (1., vec![onset(1.01, 0.6), onset(3., 0.4)]),
+                (1., vec![onset(1.02, 0.4)]),
+            ],
+        );
+        let expected = [Onset { time: 1., strength: 6., salience: 0.6, agreement: 3 }, onset(2., 0.9), onset(3., 0.4)];
+        assert_eq!(combined, expected);
+
+        // The peak picker reports the values of the detection function, scaled to its range
+        let (track, truth) = TestSignal::drum_pattern(120., 10.).generate();
+        let members: Vec<(f64, Vec<Onset>)> = [(1024, 441), (2048, 1024)]
+            .into_iter()
+            .map(|(window_size, hop_size)| {
+                let input = OnsetInput::from_track(
+                    &track,
+                    Downmix::Mid,
+                    window_size,
+                    hop_size,
+                    WindowFunction::Hann,
+                    Padding::Zero,
+                );
+                let output = LFSF::default().find_onsets(&input);
+                let onsets = PICKER.pick(&output).unwrap().onset_times(&track).onsets;
+                for onset in &onsets {
+                    assert_eq!(onset.strength, output.result.data[output.result.frame_at(onset.time)]);
+                    assert!((0. ..=1.).contains(&onset.salience));
+                }
+                (1., onsets)
+            })
+            .collect();
+        let times = members
+            .iter()
+            .map(|(score, onsets)| (*score, onsets.iter().map(|onset| onset.time).collect()))
+            .collect();
+
+        // Combining keeps the onsets of combine_onsets, all of them found by both configurations
+        let combined = combine_detected_onsets(1., members);
+        let combined_times: Vec<f64> = combined.iter().map(|onset| onset.time).collect();
+        assert_eq!(combined_times, combine_onsets(1., times));
+        assert!(combined.iter().all(|onset| onset.agreement == 2));
+        assert!(f_measure(&combined_times, &truth.onsets, ONSET_ACCURACY).f_measure > 0.9);
+    }
+}
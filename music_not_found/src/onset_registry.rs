@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::constants::{LFSF_LOG_LAMBDA, MEL_BANDS};
+use crate::error::{Error, Result};
+use crate::helpers::{Padding, WindowFunction};
+use crate::onset_algorithms::{
+    merge_band_onsets, AdaptiveWhitening, BandGroup, BandLFSF, Binning, ComplexDomain, Distance, EnergyEnvelope, Envelope, HighFrequencyContent, Magnitude,
+    NormalizedWeightedPhaseDeviation, OnsetAlgorithm, OnsetInput, OnsetOutput, PhaseDeviation, SpectralDifference,
+    SpectralFlux, SuperFlux, WeightedPhaseDeviation, LFSF,
+};
+use crate::peak_picking::{Onset, TimedPeakPicker};
+use crate::post_processing::{post_process, PostProcessing};
+use crate::track::Track;
+
+/// Names of all registered onset algorithms with a short description and their own parameters
+pub const ONSET_ALGORITHMS: [(&str, &str); 10] = [
+    ("lfsf", "log-filtered spectral flux [lambda, bands, fmin, fmax, lag, band=low|mid|high|each]"),
+    ("superflux", "SuperFlux [lambda, bands, fmin, fmax, width, lag]"),
+    (
+        "flux",
+        "spectral flux family [binning=linear|mel|log, bands, fmin, fmax, magnitude=linear|log, lambda, \
+        norm=l1|l2|mkl, lag]",
+    ),
+    ("sd", "spectral difference"),
+    ("hfc", "high frequency content"),
+    ("cd", "complex domain [rectified]"),
+    ("pd", "phase deviation"),
+    ("wpd", "weighted phase deviation"),
+    ("nwpd", "normalised weighted phase deviation"),
+    ("energy", "energy envelope [log, octaves, lowest]"),
+];
+
+/// Parameters every onset algorithm accepts; max, mean and dist are in milliseconds, post is a list
+/// of post-processing steps (see `PostProcessing::from_name`), e.g. post=log,normalize, and whiten
+/// is the relaxation time (in ms) of adaptive whitening with the given floor
+pub const COMMON_PARAMETERS: &str = "win, hop, window, padding, score, max, mean, dist, delta, post, whiten, floor";
+
+/// An onset algorithm of the ensemble together with the stft and the peak picker it is used with
+#[derive(Clone)]
+pub struct OnsetSpec {
+    pub algorithm: Arc<dyn OnsetAlgorithm + Send + Sync>,
+    pub window_size: usize,
+    pub hop_size: usize,
+    pub window_function: WindowFunction,
+    /// How the signal is extended for the first and the last stft frames
+    pub padding: Padding,
+    /// Applied to the stft before the algorithm, if given
+    pub whitening: Option<AdaptiveWhitening>,
+    /// Applied to the detection function before peak picking
+    pub post_processing: Vec<PostProcessing>,
+    pub peak_picker: TimedPeakPicker,
+    /// Picks the peaks of each band group of the detection function separately and merges them,
+    /// instead of picking the peaks of the sum
+    pub pick_bands: bool,
+    /// Weight of the onsets of the algorithm in the ensemble, e.g. its F-score on a training set
+    pub score: f64,
+}
+
+impl OnsetSpec {
+    /// The algorithm on a zero padded stft with a Hamming window, without whitening and
+    /// post-processing, with the default peak picker and score 1
+    pub fn new(algorithm: Arc<dyn OnsetAlgorithm + Send + Sync>, window_size: usize, hop_size: usize) -> OnsetSpec {
+        OnsetSpec {
+            algorithm,
+            window_size,
+            hop_size,
+            window_function: WindowFunction::Hamming,
+            padding: Padding::Zero,
+            whitening: None,
+            post_processing: Vec::new(),
+            peak_picker: TimedPeakPicker::default(),
+            pick_bands: false,
+            score: 1.,
+        }
+    }
+
+    /// Computes the post-processed detection function of the algorithm on the (whitened) input
+    pub fn find_onsets(&self, input: OnsetInput) -> OnsetOutput {
+        let input = match &self.whitening {
+            Some(whitening) => input.whiten(whitening),
+            None => input,
+        };
+        post_process(self.algorithm.find_onsets(&input), &self.post_processing)
+    }
+
+    /// Picks the onsets of a detection function of the algorithm, see `pick_bands`
+    pub fn pick_onsets(&self, output: &OnsetOutput, track: &Track) -> Result<Vec<Onset>> {
+        if self.pick_bands {
+            let band_onsets = self
+                .peak_picker
+                .pick_bands(output)?
+                .iter()
+                .map(|peaks| peaks.onset_times(track).onsets)
+                .collect();
+            Ok(merge_band_onsets(band_onsets))
+        } else {
+            Ok(self.peak_picker.pick(output)?.onset_times(track).onsets)
+        }
+    }
+
+    /// Creates an onset algorithm from a specification like `lfsf:win=2048,hop=1024,lambda=0.7`.
+    /// Parameters that are not given keep the defaults of the algorithm, except for the lambda of
+    /// the LFSF, which defaults to the one of the submission; the stft defaults to a Hamming window
+    /// of size 1024 and hop size 441.
+    pub fn parse(spec: &str) -> Result<OnsetSpec> {
+        let (name, parameters) = spec.split_once(':').unwrap_or((spec, ""));
+        let mut parameters = Parameters::parse(name, parameters)?;
+
+        let window_size = parameters.take("win")?.unwrap_or(1024);
+        let hop_size = parameters.take("hop")?.unwrap_or(441);
+        if window_size == 0 || hop_size == 0 {
+            return Err(Error::InvalidOptions(format!("{}: window and hop size must be positive", name)));
+        }
+        let window_function = match parameters.take::<String>("window")? {
+            Some(window) => WindowFunction::from_name(&window)
+                .ok_or_else(|| Error::InvalidOptions(format!("{}: unknown window function {}", name, window)))?,
+            None => WindowFunction::Hamming,
+        };
+        let padding = match parameters.take::<String>("padding")? {
+            Some(padding) => Padding::from_name(&padding)
+                .ok_or_else(|| Error::InvalidOptions(format!("{}: unknown padding {}", name, padding)))?,
+            None => Padding::Zero,
+        };
+        let mut peak_picker = TimedPeakPicker::default();
+        let mut pick_bands = false;
+
+        let algorithm: Arc<dyn OnsetAlgorithm + Send + Sync> = match name {
+            "lfsf" => {
+                let default = LFSF::default();
+                let lfsf = LFSF {
+                    log_lambda: parameters.take("lambda")?.unwrap_or(LFSF_LOG_LAMBDA),
+                    mel_bands: parameters.take("bands")?.unwrap_or(default.mel_bands),
+                    min_frequency: parameters.take("fmin")?.or(default.min_frequency),
+                    max_frequency: parameters.take("fmax")?.or(default.max_frequency),
+                    lag: parameters.take_lag()?.unwrap_or(default.lag),
+                };
+                let groups = BandGroup::low_mid_high();
+                match parameters.take::<String>("band")?.as_deref() {
+                    None => Arc::new(lfsf),
+                    Some("each") => {
+                        pick_bands = true;
+                        Arc::new(BandLFSF { lfsf, groups, selected: None })
+                    }
+                    Some(band) => {
+                        let selected = groups.iter().position(|group| group.name == band);
+                        if selected.is_none() {
+                            return Err(Error::InvalidOptions(format!("{}: unknown band group {}", name, band)));
+                        }
+                        Arc::new(BandLFSF { lfsf, groups, selected })
+                    }
+                }
+            }
+            "superflux" => {
+                let default = SuperFlux::default();
+                peak_picker = SuperFlux::peak_picker();
+                Arc::new(SuperFlux {
+                    log_lambda: parameters.take("lambda")?.unwrap_or(default.log_lambda),
+                    bands_per_octave: parameters.take("bands")?.unwrap_or(default.bands_per_octave),
+                    min_frequency: parameters.take("fmin")?.unwrap_or(default.min_frequency),
+                    max_frequency: parameters.take("fmax")?.unwrap_or(default.max_frequency),
+                    max_filter_width: parameters.take("width")?.unwrap_or(default.max_filter_width),
+                    lag: parameters.take_lag()?.unwrap_or_else(|| SuperFlux::lag_for(window_size, hop_size)),
+                })
+            }
+            "flux" => {
+                let binning = match parameters.take::<String>("binning")?.as_deref() {
+                    Some("linear") => Binning::Linear,
+                    Some("mel") | None => Binning::Mel { bands: parameters.take("bands")?.unwrap_or(MEL_BANDS) },
+                    Some("log") => {
+                        let default = SuperFlux::default();
+                        Binning::LogFrequency {
+                            bands_per_octave: parameters.take("bands")?.unwrap_or(default.bands_per_octave),
+                            min_frequency: parameters.take("fmin")?.unwrap_or(default.min_frequency),
+                            max_frequency: parameters.take("fmax")?.unwrap_or(default.max_frequency),
+                        }
+                    }
+                    Some(binning) => return Err(Error::InvalidOptions(format!("{}: unknown binning {}", name, binning))),
+                };
+                let magnitude = match parameters.take::<String>("magnitude")?.as_deref() {
+                    Some("linear") => Magnitude::Linear,
+                    Some("log") | None => {
+                        Magnitude::Log { lambda: parameters.take("lambda")?.unwrap_or(LFSF_LOG_LAMBDA) }
+                    }
+                    Some(magnitude) => {
+                        return Err(Error::InvalidOptions(format!("{}: unknown magnitude {}", name, magnitude)));
+                    }
+                };
+                let distance = match parameters.take::<String>("norm")?.as_deref() {
+                    Some("l1") | None => Distance::L1,
+                    Some("l2") => Distance::L2,
+                    Some("mkl") => Distance::ModifiedKullbackLeibler,
+                    Some(norm) => return Err(Error::InvalidOptions(format!("{}: unknown norm {}", name, norm))),
+                };
+                Arc::new(SpectralFlux { binning, magnitude, distance, lag: parameters.take_lag()?.unwrap_or(1) })
+            }
+            "sd" => Arc::new(SpectralDifference),
+            "hfc" => Arc::new(HighFrequencyContent),
+            "cd" => Arc::new(ComplexDomain { rectified: parameters.take("rectified")?.unwrap_or(false) }),
+            "pd" => Arc::new(PhaseDeviation),
+            "wpd" => Arc::new(WeightedPhaseDeviation),
+            "nwpd" => Arc::new(NormalizedWeightedPhaseDeviation),
+            "energy" => {
+                let envelope = match parameters.take("log")?.unwrap_or(true) {
+                    true => Envelope::LogEnergy,
+                    false => Envelope::Rms,
+                };
+                let octaves = parameters.take("octaves")?.unwrap_or(0);
+                let lowest = parameters.take("lowest")?.unwrap_or(50.);
+                Arc::new(EnergyEnvelope { envelope, bands: EnergyEnvelope::octave_bands(lowest, octaves) })
+            }
+            _ => return Err(Error::InvalidOptions(format!("unknown onset algorithm {}", name))),
+        };
+
+        peak_picker = TimedPeakPicker {
+            local_window_max: parameters.take("max")?.unwrap_or(peak_picker.local_window_max),
+            local_window_mean: parameters.take("mean")?.unwrap_or(peak_picker.local_window_mean),
+            minimum_distance: parameters.take("dist")?.unwrap_or(peak_picker.minimum_distance),
+            delta: parameters.take("delta")?.unwrap_or(peak_picker.delta),
+        };
+        let post_processing = match parameters.take::<String>("post")? {
+            Some(chain) => chain
+                .split(',')
+                .map(|step| {
+                    PostProcessing::from_name(step)
+                        .ok_or_else(|| Error::InvalidOptions(format!("{}: unknown post-processing step {}", name, step)))
+                })
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        let whitening = match (parameters.take::<f32>("whiten")?, parameters.take("floor")?) {
+            (Some(relaxation_time), _) if relaxation_time <= 0. => {
+                return Err(Error::InvalidOptions(format!("{}: whiten must be positive", name)));
+            }
+            (Some(relaxation_time), floor) => Some(AdaptiveWhitening {
+                relaxation_time: relaxation_time / 1000.,
+                floor: floor.unwrap_or(AdaptiveWhitening::default().floor),
+            }),
+            (None, Some(_)) => return Err(Error::InvalidOptions(format!("{}: floor needs whiten", name))),
+            (None, None) => None,
+        };
+        let score = parameters.take("score")?.unwrap_or(1.);
+        parameters.finish()?;
+
+        Ok(OnsetSpec {
+            window_function,
+            padding,
+            whitening,
+            post_processing,
+            peak_picker,
+            pick_bands,
+            score,
+            ..OnsetSpec::new(algorithm, window_size, hop_size)
+        })
+    }
+}
+
+/// The `key=value` parameters of a specification; every parameter has to be used exactly once.
+/// A value may be a list: entries without `=` are appended to the value before them, so
+/// `post=log,normalize` is the value `log,normalize`.
+struct Parameters<'a> {
+    name: &'a str,
+    values: HashMap<&'a str, String>,
+}
+
+impl<'a> Parameters<'a> {
+    fn parse(name: &'a str, parameters: &'a str) -> Result<Parameters<'a>> {
+        let mut values: HashMap<&str, String> = HashMap::new();
+        let mut last_key = None;
+        for parameter in parameters.split(',').filter(|parameter| !parameter.is_empty()) {
+            match (parameter.split_once('='), last_key) {
+                (Some((key, value)), _) => {
+                    if values.insert(key, value.to_owned()).is_some() {
+                        return Err(Error::InvalidOptions(format!("{}: {} is given twice", name, key)));
+                    }
+                    last_key = Some(key);
+                }
+                (None, Some(key)) => {
+                    let value = values.get_mut(key).expect("inserted before");
+                    value.push(',');
+                    value.push_str(parameter);
+                }
+                (None, None) => {
+                    return Err(Error::InvalidOptions(format!("{}: expected key=value, got {}", name, parameter)));
+                }
+            }
+        }
+        Ok(Parameters { name, values })
+    }
+
+    /// Removes a parameter and parses its value
+    fn take<T: FromStr>(&mut self, key: &str) -> Result<Option<T>> {
+        match self.values.remove(key) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| Error::InvalidOptions(format!("{}: invalid value {} for {}", self.name, value, key))),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the lag of a flux function, which has to be at least 1
+    fn take_lag(&mut self) -> Result<Option<usize>> {
+        match self.take("lag")? {
+            Some(0) => Err(Error::InvalidOptions(format!("{}: lag must be at least 1", self.name))),
+            lag => Ok(lag),
+        }
+    }
+
+    /// Fails if there are parameters the algorithm does not know
+    fn finish(self) -> Result<()> {
+        let mut unknown: Vec<_> = self.values.into_keys().collect();
+        unknown.sort_unstable();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidOptions(format!("{}: unknown parameters {}", self.name, unknown.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_signals::TestSignal;
+    use crate::track::Downmix;
+
+    #[test]
+    fn specifications_are_parsed() {
+        let spec = OnsetSpec::parse("lfsf:win=2048,hop=1024,window=hann,score=0.75").unwrap();
+        assert_eq!((spec.window_size, spec.hop_size), (2048, 1024));
+        assert_eq!(spec.window_function, WindowFunction::Hann);
+        assert_eq!(spec.padding, Padding::Zero);
+        assert_eq!(OnsetSpec::parse("sd:padding=reflect").unwrap().padding, Padding::Reflect);
+        assert_eq!(spec.score, 0.75);
+        assert_eq!(spec.peak_picker, TimedPeakPicker::default());
+
+        let spec = OnsetSpec::parse("superflux:hop=441,delta=2,max=20").unwrap();
+        assert_eq!(spec.peak_picker, TimedPeakPicker { delta: 2., local_window_max: 20., ..SuperFlux::peak_picker() });
+
+        let spec = OnsetSpec::parse("hfc:post=log-100,normalize,win=2048").unwrap();
+        assert_eq!(spec.window_size, 2048);
+        assert_eq!(
+            spec.post_processing,
+            vec![PostProcessing::LogCompression { lambda: 100. }, PostProcessing::NormalizeMax]
+        );
+        assert_eq!(spec.whitening, None);
+
+        let spec = OnsetSpec::parse("sd:whiten=500,floor=0.01").unwrap();
+        assert_eq!(spec.whitening, Some(AdaptiveWhitening { relaxation_time: 0.5, floor: 0.01 }));
+        let spec = OnsetSpec::parse("sd:whiten=1000").unwrap();
+        assert_eq!(spec.whitening, Some(AdaptiveWhitening::default()));
+
+        let spec = OnsetSpec::parse("flux:binning=log,bands=12,fmax=8000,magnitude=linear,norm=mkl,lag=2").unwrap();
+        let expected = SpectralFlux {
+            binning: Binning::LogFrequency { bands_per_octave: 12, min_frequency: 30., max_frequency: 8000. },
+            magnitude: Magnitude::Linear,
+            distance: Distance::ModifiedKullbackLeibler,
+            lag: 2,
+        };
+        let (track, _) = TestSignal::drum_pattern(110., 2.).generate();
+        let input = || OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, Padding::Zero);
+        assert_eq!(spec.find_onsets(input()).result.data, expected.find_onsets(&input()).result.data);
+
+        // Without parameters, lfsf and flux are the LFSF of the default ensemble
+        let lfsf = LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() }.find_onsets(&input()).result.data;
+        assert_eq!(OnsetSpec::parse("lfsf").unwrap().find_onsets(input()).result.data, lfsf);
+        assert_eq!(OnsetSpec::parse("flux").unwrap().find_onsets(input()).result.data, lfsf);
+
+        let spec = OnsetSpec::parse("lfsf:band=each").unwrap();
+        assert!(spec.pick_bands);
+        assert_eq!(spec.find_onsets(input()).bands.len(), 3);
+        let spec = OnsetSpec::parse("lfsf:band=high").unwrap();
+        assert!(!spec.pick_bands);
+        let output = spec.find_onsets(input());
+        assert!(output.bands.is_empty());
+        let bands = LFSF { log_lambda: LFSF_LOG_LAMBDA, ..LFSF::default() }.find_band_onsets(&input(), &BandGroup::low_mid_high()).bands;
+        assert_eq!(output.result.data, bands[2].result.data);
+
+        for name in ONSET_ALGORITHMS.iter().map(|(name, _)| name) {
+            assert!(OnsetSpec::parse(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_specifications_are_rejected() {
+        for spec in [
+            "unknown",
+            "sd:lambda=1",
+            "lfsf:win=big",
+            "lfsf:win",
+            "lfsf:hop=0",
+            "lfsf:lag=1,lag=2",
+            "hfc:window=triangle",
+            "hfc:padding=wrap",
+            "hfc:post=log,smooth",
+            "hfc:win=1024,normalize",
+            "sd:whiten=0",
+            "sd:floor=0.01",
+            "lfsf:band=bass",
+            "lfsf:lag=0",
+            "superflux:lag=0",
+            "flux:lag=0",
+            "flux:binning=bark",
+            "flux:norm=l3",
+            "flux:binning=linear,bands=10",
+            "flux:binning=mel,fmin=100",
+            "flux:magnitude=linear,lambda=2",
+        ] {
+            assert!(
+                matches!(OnsetSpec::parse(spec), Err(Error::InvalidOptions(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+}
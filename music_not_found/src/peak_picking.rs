@@ -3,21 +3,40 @@ use std::{
     iter::repeat,
 };
 
-use crate::{helpers::WinVec, onset_algorithms::OnsetOutput, track::Track};
+use crate::{error::{Error, Result}, helpers::WinVec, onset_algorithms::OnsetOutput, track::Track};
 
 pub struct OnsetTimes {
     pub onset_times: Vec<f64>,
-    pub highest_first_beat: usize,
+    /// The same onsets together with their strength
+    pub onsets: Vec<Onset>,
+    pub highest_first_beat: Option<usize>,
+}
+
+/// An onset together with how confident the detection is, so that it can be thresholded later on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Onset {
+    pub time: f64,
+    /// Value of the detection function at the onset
+    pub strength: f32,
+    /// The strength scaled to the range of the detection function: 0 at its minimum, 1 at its maximum
+    pub salience: f32,
+    /// Number of onset algorithms that found the onset; 1 until onsets are combined by `combine_onsets`
+    pub agreement: usize,
 }
 
 pub struct Peaks {
     /// Vector of all of the length of the input signal; values at the indices of onsets have value true; all others false
     pub peaks: WinVec<bool>,
-    /// Index of the first local maxima among the onsets
-    pub highest_first_beat_index: usize,
+    /// Value of the detection function at each peak, in the order of the peaks
+    pub strengths: Vec<f32>,
+    /// Minimum and maximum of the detection function
+    pub range: (f32, f32),
+    /// Index of the first local maxima among the onsets; None if there is no such onset
+    pub highest_first_beat_index: Option<usize>,
 }
 
 /// Structure for the PeakPicking parameters, according to LFSF Peak Picking (Slide L04 62)
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct PeakPicker {
     /// == w1 == w2
     pub local_window_max: usize,
@@ -31,15 +50,120 @@ pub struct PeakPicker {
     pub minimum_distance: usize,
 }
 
+/// PeakPicker parameters in milliseconds instead of frames, so that one configuration behaves the
+/// same for all hop sizes and sample rates. They are converted to frames with the hop size and the
+/// sample rate of the detection function.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimedPeakPicker {
+    /// Time (in ms) before and after a peak in which it has to be the maximum
+    pub local_window_max: f64,
+
+    /// Time (in ms) before and after a peak whose mean it has to exceed by delta
+    pub local_window_mean: f64,
+
+    pub delta: f32,
+
+    /// Minimum time (in ms) between two peaks
+    pub minimum_distance: f64,
+}
+
+impl Default for TimedPeakPicker {
+    /// The peak picker tuned for LFSF on the train dataset: 7, 7 and 3 frames with hop size 441, and
+    /// 3, 3 and 1 frames with hop size 1024, at 44.1 kHz
+    fn default() -> TimedPeakPicker {
+        TimedPeakPicker {
+            local_window_max: 70.,
+            local_window_mean: 70., // the higher, the lower the recall but precision slightly increases
+            delta: 0.1, // must be relatively tiny
+            minimum_distance: 30.,
+        }
+    }
+}
+
+impl TimedPeakPicker {
+    /// The peak picker in frames for detection functions with the given sample rate and hop size
+    pub fn in_frames(&self, sample_rate: u32, hop_size: usize) -> PeakPicker {
+        let frames = |milliseconds: f64| (milliseconds / 1000. * sample_rate as f64 / hop_size as f64).round() as usize;
+        PeakPicker {
+            local_window_max: frames(self.local_window_max),
+            local_window_mean: frames(self.local_window_mean),
+            delta: self.delta,
+            minimum_distance: frames(self.minimum_distance),
+        }
+    }
+
+    pub fn pick(&self, onset_output: &OnsetOutput) -> Result<Peaks> {
+        let result = &onset_output.result;
+        self.in_frames(result.sample_rate, result.hop_size).pick(onset_output)
+    }
+
+    /// Picks the peaks of the detection function of each band group separately
+    pub fn pick_bands(&self, onset_output: &OnsetOutput) -> Result<Vec<Peaks>> {
+        let result = &onset_output.result;
+        self.in_frames(result.sample_rate, result.hop_size).pick_bands(onset_output)
+    }
+}
+
 /// PeakPicking implementation (Slide L04 62)
 impl PeakPicker {
-    pub fn pick(&self, onset_output: &OnsetOutput) -> Peaks {
+    pub fn pick(&self, onset_output: &OnsetOutput) -> Result<Peaks> {
         // Compute times of peaks
         let output = &onset_output.result.data;
 
+        // A peak needs a smaller value before and after it
+        if output.len() < 3 {
+            return Err(Error::TrackTooShort);
+        }
+
         // Initialize the output vector
         let mut peaks: Vec<bool> = repeat(false).take(output.len()).collect();
 
+        // Let the peak conditions iterate over the output of the onset detection function
+        for i in 1..output.len() - 1 {
+            peaks[i] = self.is_peak(output, &peaks, i);
+        }
+
+
+        // In the following, the index of the onset which is the first local maxima (according to its onset detection function value) of all onsets is determined. This necessary for proper beat detection.
+
+        let peaks_with_values = peaks.clone().into_iter().zip(output.into_iter()).filter(|&val| val.0 == true).collect::<Vec<(bool, &f32)>>();
+
+        let highest_first_beat_index = (0..peaks_with_values.len().saturating_sub(1))
+            .find(|&i| peaks_with_values[i].1 > peaks_with_values[i + 1].1);
+
+        let strengths = peaks_with_values.iter().map(|&(_, &value)| value).collect();
+        let range = output
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &value| (low.min(value), high.max(value)));
+
+        Ok(Peaks {
+            peaks: onset_output.result.set_data(peaks),
+            strengths,
+            range,
+            highest_first_beat_index,
+        })
+    }
+
+    /// Picks the peaks of the detection function of each band group separately
+    pub fn pick_bands(&self, onset_output: &OnsetOutput) -> Result<Vec<Peaks>> {
+        (0..onset_output.bands.len())
+            .map(|i| self.pick(&onset_output.band(i)))
+            .collect()
+    }
+
+    /// Number of values before an index that are needed to decide whether it is a peak
+    pub fn context(&self) -> usize {
+        max(self.lookahead(), self.minimum_distance)
+    }
+
+    /// Number of values after an index that are needed to decide whether it is a peak
+    pub fn lookahead(&self) -> usize {
+        max(max(self.local_window_max, self.local_window_mean), 1)
+    }
+
+    /// Checks whether the value at index i is a peak. `peaks` has to contain the decisions for
+    /// all indices before i. Windows are cut at the start and end of `output`.
+    pub fn is_peak(&self, output: &[f32], peaks: &[bool], i: usize) -> bool {
         // Function that computes the mean of inside a window
         let mean_window = |mean_left, mean_right| {
             output[mean_left..mean_right].iter().sum::<f32>() / (mean_right - mean_left) as f32
@@ -60,39 +184,17 @@ impl PeakPicker {
             !v
         };
 
-        // Let the three functions iterate over the output of the onset detection function
-        for i in 1..output.len() - 1 {
-            let mean_left = max(i, self.local_window_mean) - self.local_window_mean;
-            let mean_right = min(output.len(), i + self.local_window_mean + 1);
-
-            let max_left = max(i, self.local_window_max) - self.local_window_max;
-            let max_right = min(output.len(), i + self.local_window_max + 1);
-
-            peaks[i] = output[i - 1] < output[i] && output[i] > output[i + 1]  // checks if a peak
-                // implement adaptive peak picking
-                && minimum_distance(i, &peaks)
-                && output[i] >= mean_window(mean_left, mean_right) + self.delta
-                && output[i] >= max_window(max_left, max_right);
-        }
+        let mean_left = max(i, self.local_window_mean) - self.local_window_mean;
+        let mean_right = min(output.len(), i + self.local_window_mean + 1);
 
+        let max_left = max(i, self.local_window_max) - self.local_window_max;
+        let max_right = min(output.len(), i + self.local_window_max + 1);
 
-        // In the following, the index of the onset which is the first local maxima (according to its onset detection function value) of all onsets is determined. This necessary for proper beat detection.
-
-        let peaks_with_values = peaks.clone().into_iter().zip(output.into_iter()).filter(|&val| val.0 == true).collect::<Vec<(bool, &f32)>>();
-
-        let mut highest_first_beat_index: i32 = -1;
-        let mut i = 0;
-
-        while highest_first_beat_index == -1 {
-            if peaks_with_values[i].1 > peaks_with_values[i + 1].1 {
-                highest_first_beat_index = i as i32;
-            } else { i += 1 }
-        }
-
-        Peaks {
-            peaks: onset_output.result.set_data(peaks),
-            highest_first_beat_index: highest_first_beat_index as usize,
-        }
+        output[i - 1] < output[i] && output[i] > output[i + 1]  // checks if a peak
+            // implement adaptive peak picking
+            && minimum_distance(i, peaks)
+            && output[i] >= mean_window(mean_left, mean_right) + self.delta
+            && output[i] >= max_window(max_left, max_right)
     }
 }
 
@@ -103,14 +205,45 @@ impl Peaks {
 
         for i in 0..self.peaks.data.len() {
             if self.peaks.data[i] {
-                onset_times.push(
-                    (i as f64 + 1.5)
-                        * (self.peaks.hop_size as f64 / (track.header.sample_rate as f64)),
-                );
+                // Times are relative to the start of the file, even if the track is only a part of it
+                onset_times.push(track.start_time + self.peaks.frame_time(i));
             }
         }
 
+        let (low, high) = self.range;
+        let onsets = onset_times
+            .iter()
+            .zip(&self.strengths)
+            .map(|(&time, &strength)| Onset {
+                time,
+                strength,
+                salience: if high > low { (strength - low) / (high - low) } else { 1. },
+                agreement: 1,
+            })
+            .collect();
+
         let highes_first_beat = self.highest_first_beat_index; // necessary for beat detection
-        OnsetTimes { onset_times, highest_first_beat: highes_first_beat }
+        OnsetTimes { onset_times, onsets, highest_first_beat: highes_first_beat }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timed_peak_picker_has_the_same_windows_for_all_resolutions() {
+        // The default reproduces the hand-tuned pickers of both LFSF configurations
+        let picker = TimedPeakPicker::default();
+        let frames = |local_window_max, minimum_distance| PeakPicker {
+            local_window_max,
+            local_window_mean: local_window_max,
+            delta: 0.1,
+            minimum_distance,
+        };
+        assert_eq!(picker.in_frames(44100, 441), frames(7, 3));
+        assert_eq!(picker.in_frames(44100, 1024), frames(3, 1));
+        assert_eq!(picker.in_frames(48000, 480), frames(7, 3));
+        assert_eq!(picker.in_frames(88200, 882), picker.in_frames(44100, 441));
     }
 }
@@ -0,0 +1,210 @@
+use std::cmp::min;
+
+use crate::onset_algorithms::{BandOutput, OnsetOutput};
+
+/// A single step of post-processing a detection function before peak picking. Windows are cut at
+/// the start and the end of the detection function, like in the peak picking.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PostProcessing {
+    /// Divides by the maximum absolute value, so that the values lie within [-1, 1] and the highest
+    /// one of non-negative values is 1; (almost) silent values are kept as they are
+    NormalizeMax,
+    /// Subtracts the mean and divides by the standard deviation
+    ZScore,
+    /// Mean of each value and the given number of values before and after it
+    MovingAverage { radius: usize },
+    /// Gaussian smoothing with the given standard deviation (in frames)
+    Gaussian { sigma: f32 },
+    /// log10(lambda * x + 1)
+    LogCompression { lambda: f32 },
+    /// Subtracts the mean of each value and the given number of values before and after it
+    SubtractLocalMean { radius: usize },
+    /// Sets negative values to 0
+    HalfWaveRectify,
+}
+
+impl PostProcessing {
+    /// Parses the name of a step as used on the command line: `normalize`, `zscore`, `average-R`,
+    /// `gauss-SIGMA`, `log` (lambda 1) or `log-LAMBDA`, `subtract-mean-R` and `rectify`, where R is
+    /// a radius in frames
+    pub fn from_name(name: &str) -> Option<PostProcessing> {
+        match name {
+            "normalize" => Some(PostProcessing::NormalizeMax),
+            "zscore" => Some(PostProcessing::ZScore),
+            "log" => Some(PostProcessing::LogCompression { lambda: 1. }),
+            "rectify" => Some(PostProcessing::HalfWaveRectify),
+            _ => {
+                if let Some(radius) = name.strip_prefix("average-") {
+                    Some(PostProcessing::MovingAverage { radius: radius.parse().ok()? })
+                } else if let Some(sigma) = name.strip_prefix("gauss-") {
+                    let sigma: f32 = sigma.parse().ok()?;
+                    (sigma > 0.).then_some(PostProcessing::Gaussian { sigma })
+                } else if let Some(lambda) = name.strip_prefix("log-") {
+                    Some(PostProcessing::LogCompression { lambda: lambda.parse().ok()? })
+                } else if let Some(radius) = name.strip_prefix("subtract-mean-") {
+                    Some(PostProcessing::SubtractLocalMean { radius: radius.parse().ok()? })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Applies the step to a detection function
+    pub fn apply(&self, values: &[f32]) -> Vec<f32> {
+        match *self {
+            PostProcessing::NormalizeMax => {
+                let max = values.iter().fold(0f32, |max, x| max.max(x.abs()));
+                if max <= f32::EPSILON {
+                    return values.to_vec();
+                }
+                values.iter().map(|x| x / max).collect()
+            }
+            PostProcessing::ZScore => {
+                let n = values.len().max(1) as f32;
+                let mean = values.iter().sum::<f32>() / n;
+                let deviation = (values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n).sqrt();
+                let deviation = if deviation > 0. { deviation } else { 1. };
+                values.iter().map(|x| (x - mean) / deviation).collect()
+            }
+            PostProcessing::MovingAverage { radius } => local_means(values, radius),
+            PostProcessing::Gaussian { sigma } => {
+                let radius = (3. * sigma).ceil() as usize;
+                let kernel: Vec<f32> = (0..=2 * radius)
+                    .map(|i| (-((i as f32 - radius as f32) / sigma).powi(2) / 2.).exp())
+                    .collect();
+                (0..values.len())
+                    .map(|i| {
+                        // The kernel is cut at the edges and scaled to a sum of 1 again
+                        let (sum, weights) = (i.saturating_sub(radius)..min(values.len(), i + radius + 1))
+                            .map(|j| (values[j], kernel[j + radius - i]))
+                            .fold((0., 0.), |(sum, weights), (x, weight)| (sum + x * weight, weights + weight));
+                        sum / weights
+                    })
+                    .collect()
+            }
+            PostProcessing::LogCompression { lambda } => values.iter().map(|x| (lambda * x + 1.).log10()).collect(),
+            PostProcessing::SubtractLocalMean { radius } => values
+                .iter()
+                .zip(local_means(values, radius))
+                .map(|(x, mean)| x - mean)
+                .collect(),
+            PostProcessing::HalfWaveRectify => values.iter().map(|&x| f32::max(x, 0.)).collect(),
+        }
+    }
+}
+
+/// Applies all steps of a chain, in order, to the detection function and to the detection
+/// functions of all band groups
+pub fn post_process(output: OnsetOutput, chain: &[PostProcessing]) -> OnsetOutput {
+    let process = |values: &[f32]| {
+        chain
+            .iter()
+            .fold(values.to_owned(), |values, step| step.apply(&values))
+    };
+    OnsetOutput {
+        result: output.result.set_data(process(&output.result.data)),
+        bands: output
+            .bands
+            .into_iter()
+            .map(|band| BandOutput {
+                result: band.result.set_data(process(&band.result.data)),
+                group: band.group,
+            })
+            .collect(),
+    }
+}
+
+/// Mean of each value and the given number of values before and after it
+fn local_means(values: &[f32], radius: usize) -> Vec<f32> {
+    (0..values.len())
+        .map(|i| {
+            let window = &values[i.saturating_sub(radius)..min(values.len(), i + radius + 1)];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::ONSET_ACCURACY;
+    use crate::f_measure::f_measure;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::onset_algorithms::{HighFrequencyContent, OnsetAlgorithm, OnsetInput, SpectralDifference, LFSF};
+    use crate::test_signals::{TestSignal, PICKER};
+    use crate::track::Downmix;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn steps_transform_values() {
+        let values = [0., 2., 4., 2., 0.];
+        assert_close(&PostProcessing::NormalizeMax.apply(&values), &[0., 0.5, 1., 0.5, 0.]);
+        // After the z-score, all values may be negative or zero
+        assert_close(&PostProcessing::NormalizeMax.apply(&[-4., -2., 0.]), &[-1., -0.5, 0.]);
+        assert_close(&PostProcessing::NormalizeMax.apply(&[0., 1e-9]), &[0., 1e-9]);
+        assert_close(&PostProcessing::MovingAverage { radius: 1 }.apply(&values), &[1., 2., 8. / 3., 2., 1.]);
+        assert_close(
+            &PostProcessing::SubtractLocalMean { radius: 1 }.apply(&values),
+            &[-1., 0., 4. / 3., 0., -1.],
+        );
+        assert_close(&PostProcessing::HalfWaveRectify.apply(&[-1., 0., 1.]), &[0., 0., 1.]);
+        assert_close(&PostProcessing::LogCompression { lambda: 9. }.apply(&[0., 1.]), &[0., 1.]);
+
+        let z = PostProcessing::ZScore.apply(&values);
+        assert!(z.iter().sum::<f32>().abs() < 1e-5);
+        assert!((z.iter().map(|x| x * x).sum::<f32>() / 5. - 1.).abs() < 1e-5);
+
+        // Smoothing keeps constant functions and the sum of a peak away from the edges
+        assert_close(&PostProcessing::Gaussian { sigma: 2. }.apply(&[3.; 10]), &[3.; 10]);
+        let mut peak = [0f32; 31];
+        peak[15] = 1.;
+        let smoothed = PostProcessing::Gaussian { sigma: 2. }.apply(&peak);
+        assert!((smoothed.iter().sum::<f32>() - 1.).abs() < 1e-5);
+        assert!(smoothed[15] < 0.25 && smoothed[14] == smoothed[16]);
+    }
+
+    #[test]
+    fn steps_are_parsed_from_names() {
+        assert_eq!(PostProcessing::from_name("normalize"), Some(PostProcessing::NormalizeMax));
+        assert_eq!(PostProcessing::from_name("log"), Some(PostProcessing::LogCompression { lambda: 1. }));
+        assert_eq!(PostProcessing::from_name("log-100"), Some(PostProcessing::LogCompression { lambda: 100. }));
+        assert_eq!(PostProcessing::from_name("average-3"), Some(PostProcessing::MovingAverage { radius: 3 }));
+        assert_eq!(PostProcessing::from_name("gauss-1.5"), Some(PostProcessing::Gaussian { sigma: 1.5 }));
+        assert_eq!(PostProcessing::from_name("subtract-mean-5"), Some(PostProcessing::SubtractLocalMean { radius: 5 }));
+        for name in ["gauss-0", "average-x", "smooth", ""] {
+            assert_eq!(PostProcessing::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn same_peak_picker_for_different_algorithms() {
+        let (track, truth) = TestSignal::drum_pattern(110., 10.).generate();
+        let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hann, Padding::Zero);
+
+        // The squared magnitudes of HFC and spectral difference are dominated by the loud kicks,
+        // while LFSF is already logarithmically compressed
+        let compressed = vec![
+            PostProcessing::NormalizeMax,
+            PostProcessing::LogCompression { lambda: 100. },
+            PostProcessing::NormalizeMax,
+        ];
+        let algorithms: [(&str, &dyn OnsetAlgorithm, Vec<PostProcessing>); 3] = [
+            ("hfc", &HighFrequencyContent, compressed.clone()),
+            ("sd", &SpectralDifference, compressed),
+            ("lfsf", &LFSF::default(), vec![PostProcessing::NormalizeMax]),
+        ];
+        for (name, algorithm, chain) in algorithms {
+            let output = post_process(algorithm.find_onsets(&input), &chain);
+            let found = PICKER.pick(&output).unwrap().onset_times(&track).onset_times;
+            let result = f_measure(&found, &truth.onsets, ONSET_ACCURACY);
+            assert!(result.f_measure > 0.9, "{}: {}", name, result.f_measure);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::beat_tracking_and_tempo::{get_tempo, Tempo};
+use crate::constants::{REALTIME_BEAT_TOLERANCE, REALTIME_TEMPO_INTERVAL, REALTIME_TEMPO_WINDOW};
+use crate::helpers::{Padding, WindowFunction};
+use crate::onset_algorithms::LFSF;
+use crate::peak_picking::PeakPicker;
+use crate::streaming::StreamingOnsetDetector;
+
+/// Events emitted by the real-time engine; all times are in seconds since the first sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Onset { time: f64 },
+    /// A beat together with the tempo (in BPM) that has been used to track it
+    Beat { time: f64, bpm: f64 },
+}
+
+/// Detects onsets and beats on a live stream of samples, e.g. pushed from an audio callback.
+/// Only past samples are used, so every onset is reported at most `latency()` seconds after it
+/// happened; beats without a close onset are reported REALTIME_BEAT_TOLERANCE beat periods later.
+/// The beats before the first tempo estimation are reported together as soon as it is available.
+/// The onsets are identical to the ones of the offline LFSF and peak picking.
+pub struct RealtimeEngine<F: FnMut(Event)> {
+    detector: StreamingOnsetDetector,
+    callback: F,
+    sample_rate: u32,
+    /// Number of samples pushed so far
+    received: usize,
+    /// Number of detection function values at the last tempo estimation
+    frames_at_last_tempo: usize,
+    tempo: Option<(Tempo, Tempo)>,
+
+    /// Number of onsets found so far
+    onset_count: usize,
+    /// Onsets that may still become the first beat, or that follow it while no tempo is known
+    pending_onsets: Vec<f64>,
+    /// Index of the first onset in `pending_onsets`
+    pending_offset: usize,
+    first_beat_found: bool,
+    last_beat: Option<f64>,
+}
+
+impl<F: FnMut(Event)> RealtimeEngine<F> {
+    /// Creates an engine that calls the callback for every found onset and beat
+    pub fn new(
+        lfsf: LFSF,
+        picker: PeakPicker,
+        window_size: usize,
+        hop_size: usize,
+        window_function: WindowFunction,
+        sample_rate: u32,
+        callback: F,
+    ) -> RealtimeEngine<F> {
+        let detector = StreamingOnsetDetector::new(lfsf, picker, window_size, hop_size, window_function, sample_rate);
+        let tempo_window = detector.frame_at(REALTIME_TEMPO_WINDOW);
+        RealtimeEngine {
+            detector: detector.keep_recent_detection_function(tempo_window),
+            callback,
+            sample_rate,
+            received: 0,
+            frames_at_last_tempo: 0,
+            tempo: None,
+            onset_count: 0,
+            pending_onsets: Vec::new(),
+            pending_offset: 0,
+            first_beat_found: false,
+            last_beat: None,
+        }
+    }
+
+    /// Pads the start of the stream like the stft does with the given padding (the default is zero
+    /// padding)
+    pub fn pad_with(mut self, padding: Padding) -> RealtimeEngine<F> {
+        self.detector = self.detector.pad_with(padding);
+        self
+    }
+
+    /// Reports event times relative to the given time (in seconds) instead of the first pushed sample
+    pub fn start_at(mut self, start_time: f64) -> RealtimeEngine<F> {
+        self.detector = self.detector.start_at(start_time);
+        self
+    }
+
+    /// Processes a block of samples of any length
+    pub fn push(&mut self, samples: &[f32]) {
+        self.received += samples.len();
+        let onsets = self.detector.push(samples);
+        self.handle_onsets(onsets);
+        self.update_tempo();
+
+        // Every onset before this time has been reported, so missing beats can be filled in
+        let known_time = self.detector.start_time() + self.received as f64 / self.sample_rate as f64 - self.latency();
+        self.fill_beats(known_time);
+    }
+
+    /// Processes the remaining samples after the end of the stream
+    pub fn finish(&mut self) {
+        let onsets = self.detector.finish();
+        self.handle_onsets(onsets);
+    }
+
+    /// The latest tempo estimation of the recent part of the stream
+    pub fn tempo(&self) -> Option<(Tempo, Tempo)> {
+        self.tempo
+    }
+
+    /// Maximum delay (in samples) between an onset and the push that reports it
+    pub fn latency_samples(&self) -> usize {
+        self.detector.latency_samples()
+    }
+
+    /// Maximum delay (in seconds) between an onset and the push that reports it
+    pub fn latency(&self) -> f64 {
+        self.latency_samples() as f64 / self.sample_rate as f64
+    }
+
+    fn handle_onsets(&mut self, onsets: Vec<f64>) {
+        for time in onsets {
+            (self.callback)(Event::Onset { time });
+            self.onset_count += 1;
+
+            if self.last_beat.is_some() {
+                self.track_beat(time);
+            } else {
+                self.pending_onsets.push(time);
+            }
+        }
+
+        // Like in the offline beat tracking, the first local maximum of the onsets is the first beat
+        if !self.first_beat_found {
+            match self.detector.highest_first_beat() {
+                Some(index) => {
+                    self.pending_onsets.drain(..index - self.pending_offset);
+                    self.pending_offset = index;
+                    self.first_beat_found = true;
+                }
+                None => {
+                    // Only the latest onset may still become the first beat
+                    let drop = self.pending_onsets.len().saturating_sub(1);
+                    self.pending_onsets.drain(..drop);
+                    self.pending_offset = self.onset_count - self.pending_onsets.len();
+                }
+            }
+        }
+        self.start_beats();
+    }
+
+    /// Emits the first beat and the pending onsets as soon as the first beat and the tempo are known
+    fn start_beats(&mut self) {
+        if self.last_beat.is_some() || !self.first_beat_found || self.tempo.is_none() {
+            return;
+        }
+
+        let mut pending = std::mem::take(&mut self.pending_onsets).into_iter();
+        if let Some(first_beat) = pending.next() {
+            self.emit_beat(first_beat);
+            for time in pending {
+                self.track_beat(time);
+            }
+        }
+    }
+
+    /// Re-estimates the tempo on the recent detection function in regular intervals
+    fn update_tempo(&mut self) {
+        let interval = self.detector.frame_at(REALTIME_TEMPO_INTERVAL);
+        if self.detector.frame_count() < self.frames_at_last_tempo + interval {
+            return;
+        }
+        self.frames_at_last_tempo = self.detector.frame_count();
+
+        // Too little of the stream is known for estimating the tempo yet
+        if let Ok(tempo) = get_tempo(&self.detector.detection_function().expect("is kept")) {
+            self.tempo = Some(tempo);
+            self.start_beats();
+        }
+    }
+
+    /// The beat period (in seconds) of the slower one of the two estimated tempos
+    fn beat_period(&self) -> Option<(f64, f64)> {
+        self.tempo.map(|(tempo1, tempo2)| {
+            let bpm = tempo1.bpm.min(tempo2.bpm);
+            (60. / bpm, bpm)
+        })
+    }
+
+    /// Decides whether an onset after the last beat is the next beat
+    fn track_beat(&mut self, time: f64) {
+        let (Some((period, _)), Some(_)) = (self.beat_period(), self.last_beat) else {
+            return;
+        };
+
+        // Beats without an onset close enough are added artificially, like in get_beats
+        self.fill_beats(time);
+        let last_beat = self.last_beat.expect("checked above");
+        if (last_beat + period - time).abs() <= REALTIME_BEAT_TOLERANCE * period {
+            self.emit_beat(time);
+        }
+    }
+
+    /// Adds artificial beats until the given time where no onset has been close to the next beat
+    fn fill_beats(&mut self, until: f64) {
+        let (Some((period, _)), Some(mut last_beat)) = (self.beat_period(), self.last_beat) else {
+            return;
+        };
+        while until - last_beat > (1. + REALTIME_BEAT_TOLERANCE) * period {
+            last_beat += period;
+            self.emit_beat(last_beat);
+        }
+    }
+
+    fn emit_beat(&mut self, time: f64) {
+        let bpm = self.beat_period().map_or(0., |(_, bpm)| bpm);
+        self.last_beat = Some(time);
+        (self.callback)(Event::Beat { time, bpm });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::fs::File;
+
+    use super::*;
+    use crate::onset_algorithms::{OnsetAlgorithm, OnsetInput};
+    use crate::test_signals::PICKER;
+    use crate::track::{Downmix, Track};
+
+    /// Writes clicks at 120 BPM, every second one accented, to a WAV file and reads it back
+    fn click_track() -> Track {
+        let sample_rate = 44100;
+        let samples: Vec<f32> = (0..sample_rate * 10)
+            .map(|i| {
+                let since_click = i % (sample_rate / 2);
+                let amplitude = if i % sample_rate < sample_rate / 2 { 0.8 } else { 0.4 };
+                if since_click < 200 { amplitude * (since_click as f32 * 0.3).sin() } else { 0. }
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join("music_not_found_realtime_test.wav");
+        let header = wav_io::new_header(sample_rate as u32, 16, false, true);
+        wav_io::write_to_file(&mut File::create(&path).unwrap(), &header, &samples).unwrap();
+        let track = Track::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        track
+    }
+
+    #[test]
+    fn realtime_onsets_match_offline_onsets() {
+        let track = click_track();
+        let configurations = [
+            (LFSF { log_lambda: 0.7, ..LFSF::default() }, Padding::Zero),
+            (LFSF { log_lambda: 0.7, ..LFSF::default() }, Padding::Reflect),
+            (
+                LFSF { log_lambda: 0.7, mel_bands: 64, max_frequency: Some(10000.), lag: 3, ..LFSF::default() },
+                Padding::Zero,
+            ),
+        ];
+        for (lfsf, padding) in configurations {
+            let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, padding);
+            let offline = PICKER
+                .pick(&lfsf.find_onsets(&input))
+                .unwrap()
+                .onset_times(&track)
+                .onset_times;
+
+            let mut events = Vec::new();
+            let mut engine = RealtimeEngine::new(
+                lfsf,
+                PICKER,
+                1024,
+                441,
+                WindowFunction::Hamming,
+                track.sample_rate,
+                |event| events.push(event),
+            )
+                .pad_with(padding);
+            for block in track.channels[0].chunks(500) {
+                engine.push(block);
+            }
+            engine.finish();
+
+            let online: Vec<f64> = events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Onset { time } => Some(*time),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(online, offline, "{:?} {:?}", lfsf, padding);
+        }
+    }
+
+    #[test]
+    fn realtime_events_respect_latency() {
+        let track = click_track();
+        let block_size = 256;
+        let pushed = Cell::new(0);
+        let mut events = Vec::new();
+        let mut engine = RealtimeEngine::new(
+            LFSF { log_lambda: 0.7, ..LFSF::default() },
+            PICKER,
+            1024,
+            441,
+            WindowFunction::Hamming,
+            track.sample_rate,
+            |event| events.push((event, pushed.get())),
+        );
+        let latency = engine.latency_samples();
+
+        for block in track.channels[0].chunks(block_size) {
+            pushed.set(pushed.get() + block.len());
+            engine.push(block);
+        }
+
+        let sample = |time: f64| (time * track.sample_rate as f64).round() as usize;
+        let mut tracking_since = None;
+        let mut beats = 0;
+        for (event, pushed) in events {
+            match event {
+                Event::Onset { time } => assert!(pushed <= sample(time) + latency + block_size),
+                Event::Beat { time, bpm } => {
+                    // Clicks are at 120 BPM, accented every second click
+                    assert!((bpm - 120.).abs() < 5. || (bpm - 60.).abs() < 5., "bpm {}", bpm);
+                    // Beats before the first tempo estimation are reported all at once
+                    let tracking_since = *tracking_since.get_or_insert(pushed);
+                    if sample(time) >= tracking_since {
+                        let tolerance = sample(REALTIME_BEAT_TOLERANCE * 60. / bpm);
+                        assert!(pushed <= sample(time) + tolerance + latency + block_size);
+                    }
+                    beats += 1;
+                }
+            }
+        }
+        assert!(beats >= 5);
+    }
+}
@@ -0,0 +1,716 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+
+use rubato::{FftFixedIn, Resampler};
+use rustfft::num_complex::Complex;
+
+use crate::beat_tracking_and_tempo::{Tempo, TempoEstimator};
+use crate::constants::RESAMPLER_CHUNK_SIZE;
+use crate::decoder::{AudioFormat, PacketStream};
+use crate::error::{Error, Result};
+use crate::helpers::{FrameTransform, Padding, SparseFilterbank, WinVec, WindowFunction, zeroes};
+use crate::onset_algorithms::LFSF;
+use crate::peak_picking::PeakPicker;
+use crate::track::{Downmix, Track};
+
+/*****************
+ * SAMPLE BLOCKS *
+ *****************/
+
+/// Reads an audio file block by block, resamples the blocks to the analysis sample rate and
+/// reduces them to a single channel. Only the current block is held in memory.
+pub struct SampleBlocks {
+    stream: PacketStream,
+    resampler: Option<StreamingResampler>,
+    downmix: Downmix,
+    sample_rate: u32,
+    finished: bool,
+    /// Index of the first (resampled) sample that is returned
+    start_index: usize,
+    /// Index after the last (resampled) sample that is returned
+    end_index: Option<usize>,
+    /// Index of the first sample of the next block
+    position: usize,
+}
+
+impl SampleBlocks {
+    /// Opens the file at the given path. The max-energy downmix needs to know the whole track and
+    /// can therefore not be used.
+    pub fn open(file_path: &Path, downmix: Downmix, sample_rate: u32) -> Result<SampleBlocks> {
+        if downmix == Downmix::MaxEnergy {
+            return Err(Error::InvalidOptions(
+                "the max-energy downmix needs the whole track and cannot be streamed".to_owned(),
+            ));
+        }
+        let format = AudioFormat::detect(file_path).ok_or(Error::UnsupportedFormat)?;
+
+        Ok(SampleBlocks {
+            stream: PacketStream::open(file_path, format)?,
+            resampler: None,
+            downmix,
+            sample_rate,
+            finished: false,
+            start_index: 0,
+            end_index: None,
+            position: 0,
+        })
+    }
+
+    /// Returns only the samples between the given times (in seconds), like Track::slice. Ranges
+    /// after the end of the file are only noticed while reading.
+    pub fn range(mut self, start: Option<f64>, end: Option<f64>) -> Result<SampleBlocks> {
+        let to_index = |time: f64| (time.max(0.) * self.sample_rate as f64).round() as usize;
+        self.start_index = start.map_or(0, to_index);
+        self.end_index = end.map(to_index);
+        if self.end_index.is_some_and(|end_index| end_index <= self.start_index) {
+            return Err(Error::InvalidOptions(format!(
+                "the time range {}..{} s does not contain any samples of the track",
+                start.unwrap_or(0.),
+                end.expect("end index is given"),
+            )));
+        }
+        Ok(self)
+    }
+
+    /// Time (in seconds) of the first returned sample within the file
+    pub fn start_time(&self) -> f64 {
+        self.start_index as f64 / self.sample_rate as f64
+    }
+
+    /// Sample rate of the file, before resampling
+    pub fn original_sample_rate(&self) -> u32 {
+        self.stream.sample_rate
+    }
+
+    /// Returns the next block of mono samples within the range, or None if the whole range has
+    /// been read
+    pub fn next_block(&mut self) -> Result<Option<Vec<f32>>> {
+        let end_index = self.end_index.unwrap_or(usize::MAX);
+        while self.position < end_index {
+            let Some(block) = self.next_file_block()? else {
+                break;
+            };
+            let block_start = self.position;
+            self.position += block.len();
+
+            let from = self.start_index.clamp(block_start, self.position) - block_start;
+            let to = end_index.clamp(block_start, self.position) - block_start;
+            if from < to {
+                return Ok(Some(block[from..to].to_owned()));
+            }
+        }
+
+        let has_range = self.start_index > 0 || self.end_index.is_some();
+        if has_range && self.position.min(end_index) <= self.start_index {
+            return Err(Error::InvalidOptions(
+                "the time range does not contain any samples of the track".to_owned(),
+            ));
+        }
+        Ok(None)
+    }
+
+    /// Returns the next block of mono samples of the file, or None if the whole file has been read
+    fn next_file_block(&mut self) -> Result<Option<Vec<f32>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let channels = match self.stream.next_block()? {
+            Some(block) => {
+                // The resampler is created with the first block, as only then the number of
+                // channels and the sample rate are known for sure
+                if self.resampler.is_none() {
+                    self.resampler = Some(StreamingResampler::new(self.stream.sample_rate, self.sample_rate, block.len())?);
+                }
+                self.resampler.as_mut().expect("created above").push(block)?
+            }
+            None => {
+                self.finished = true;
+                match self.resampler.as_mut() {
+                    Some(resampler) => resampler.finish()?,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        // Downmixing is done the same way as for a whole track
+        let block = Track {
+            channels,
+            sample_rate: self.sample_rate,
+            original_sample_rate: self.stream.sample_rate,
+            start_time: 0.,
+        };
+        Ok(Some(block.downmix(self.downmix)))
+    }
+}
+
+/// Resamples blocks of samples. The output is identical to resampling the whole signal at once
+/// with `Track::resample`.
+pub struct StreamingResampler {
+    /// None if the input already has the wanted sample rate
+    resampler: Option<FftFixedIn<f32>>,
+    /// Samples of each channel that have not been passed to the resampler yet
+    input: Vec<Vec<f32>>,
+    input_rate: u32,
+    output_rate: u32,
+    /// Number of samples (per channel) passed in so far
+    input_len: usize,
+    /// Number of samples (per channel) the resampler produced so far, including its delay
+    produced_len: usize,
+    /// Number of samples (per channel) returned so far
+    output_len: usize,
+}
+
+impl StreamingResampler {
+    pub fn new(input_rate: u32, output_rate: u32, channel_count: usize) -> Result<StreamingResampler> {
+        let resampler = if input_rate == output_rate {
+            None
+        } else {
+            Some(FftFixedIn::<f32>::new(
+                input_rate as usize,
+                output_rate as usize,
+                RESAMPLER_CHUNK_SIZE,
+                2,
+                channel_count,
+            )?)
+        };
+
+        Ok(StreamingResampler {
+            resampler,
+            input: vec![Vec::new(); channel_count],
+            input_rate,
+            output_rate,
+            input_len: 0,
+            produced_len: 0,
+            output_len: 0,
+        })
+    }
+
+    /// Passes a block of samples of each channel in; returns the resampled samples that are ready
+    pub fn push(&mut self, block: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>> {
+        self.input_len += block[0].len();
+        let resampler = match self.resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(block),
+        };
+
+        for (input, block_channel) in self.input.iter_mut().zip(block) {
+            input.extend(block_channel);
+        }
+
+        let mut resampled = Vec::new();
+        while self.input[0].len() >= resampler.input_frames_next() {
+            let frames = resampler.input_frames_next();
+            let chunk: Vec<&[f32]> = self.input.iter().map(|c| &c[..frames]).collect();
+            resampled.push(resampler.process(&chunk, None)?);
+            for input in self.input.iter_mut() {
+                input.drain(..frames);
+            }
+        }
+
+        let mut output = vec![Vec::new(); self.input.len()];
+        for block in resampled {
+            self.append(&mut output, block);
+        }
+        Ok(output)
+    }
+
+    /// Flushes the resampler after the last block; returns the remaining resampled samples
+    pub fn finish(&mut self) -> Result<Vec<Vec<f32>>> {
+        let mut output = vec![Vec::new(); self.input.len()];
+        let resampler = match self.resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(output),
+        };
+
+        let delay = resampler.output_delay();
+        let expected_len = (self.input_len as f64 * self.output_rate as f64 / self.input_rate as f64).round() as usize;
+
+        let rest: Vec<&[f32]> = self.input.iter().map(|c| &c[..]).collect();
+        let mut resampled = vec![resampler.process_partial(Some(&rest), None)?];
+        let mut produced_len = self.produced_len + resampled[0][0].len();
+        while produced_len < delay + expected_len {
+            resampled.push(resampler.process_partial::<&[f32]>(None, None)?);
+            produced_len += resampled.last().unwrap()[0].len();
+        }
+        self.input.iter_mut().for_each(|input| input.clear());
+
+        for block in resampled {
+            self.append(&mut output, block);
+        }
+        for channel in output.iter_mut() {
+            channel.truncate(expected_len.saturating_sub(self.output_len - channel.len()));
+        }
+        Ok(output)
+    }
+
+    /// Appends resampled samples to the output, leaving out the delay of the resampler
+    fn append(&mut self, output: &mut [Vec<f32>], resampled: Vec<Vec<f32>>) {
+        let delay = self.resampler.as_ref().map_or(0, |resampler| resampler.output_delay());
+        let skip = delay.saturating_sub(self.produced_len).min(resampled[0].len());
+
+        self.produced_len += resampled[0].len();
+        self.output_len += resampled[0].len() - skip;
+        for (channel, resampled_channel) in output.iter_mut().zip(resampled) {
+            channel.extend(&resampled_channel[skip..]);
+        }
+    }
+}
+
+/****************************
+ * STREAMING ONSET DETECTOR *
+ ****************************/
+
+/// Computes the STFT, the LFSF detection function and the peak picking incrementally on blocks
+/// of samples. Memory usage only depends on the window size, the peak picking windows and the
+/// tempo range, not on the length of the track. The found onsets are identical to the ones of the
+/// batch functions.
+pub struct StreamingOnsetDetector {
+    transform: FrameTransform,
+    /// Window size, hop size, window function and sample rate of the frames (without data); used
+    /// for converting frames to times
+    axis: WinVec<f32>,
+    padding: Padding,
+    /// Time (in seconds) of the first pushed sample within the file
+    start_time: f64,
+    /// Samples starting at the position of the next STFT frame; before the padding at the start has
+    /// been added, all pushed samples
+    samples: Vec<f32>,
+    /// Whether the padding at the start has been added
+    started: bool,
+    /// The last pushed samples, needed for the padding at the end
+    tail: Vec<f32>,
+    /// Total number of pushed samples
+    sample_count: usize,
+
+    lfsf: LFSF,
+    filterbank: Arc<SparseFilterbank>,
+    /// Log-mel spectra of the last `lfsf.lag` frames, the oldest first
+    previous: VecDeque<Vec<f32>>,
+    /// Most recent detection function values; only kept if requested
+    detection_function: Option<Vec<f32>>,
+    /// Maximum number of the most recent detection function values that are kept
+    detection_function_limit: Option<usize>,
+    /// Tempo estimation on the complete detection function; only if requested
+    tempo: Option<TempoEstimator>,
+
+    picker: PeakPicker,
+    /// Detection function values starting at frame `offset`
+    values: Vec<f32>,
+    /// Peak decisions starting at frame `offset`
+    peaks: Vec<bool>,
+    offset: usize,
+    /// Total number of detection function values
+    received: usize,
+    /// Number of peaks found so far
+    peak_count: usize,
+    last_peak_value: Option<f32>,
+    highest_first_beat: Option<usize>,
+}
+
+impl StreamingOnsetDetector {
+    /// Creates a detector; the hop size must not be larger than the window size
+    pub fn new(
+        lfsf: LFSF,
+        picker: PeakPicker,
+        window_size: usize,
+        hop_size: usize,
+        window_function: WindowFunction,
+        sample_rate: u32,
+    ) -> StreamingOnsetDetector {
+        let filterbank = lfsf.filterbank(sample_rate, window_size);
+        StreamingOnsetDetector {
+            transform: FrameTransform::new(window_size, window_function),
+            axis: WinVec {
+                window_size,
+                hop_size,
+                window_function,
+                sample_rate,
+                data: Vec::new(),
+            },
+            padding: Padding::Zero,
+            start_time: 0.,
+            samples: Vec::with_capacity(2 * window_size),
+            started: false,
+            tail: Vec::with_capacity(2 * window_size),
+            sample_count: 0,
+            lfsf,
+            previous: vec![zeroes(filterbank.len()); lfsf.lag].into(),
+            filterbank,
+            detection_function: None,
+            detection_function_limit: None,
+            tempo: None,
+            picker,
+            values: Vec::new(),
+            peaks: Vec::new(),
+            offset: 0,
+            received: 0,
+            peak_count: 0,
+            last_peak_value: None,
+            highest_first_beat: None,
+        }
+    }
+
+    /// Pads the start and the end of the stream like the stft does with the given padding (the
+    /// default is zero padding)
+    pub fn pad_with(mut self, padding: Padding) -> StreamingOnsetDetector {
+        self.padding = padding;
+        self
+    }
+
+    /// Reports onset times relative to the given time (in seconds) instead of the first pushed sample
+    pub fn start_at(mut self, start_time: f64) -> StreamingOnsetDetector {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Time (in seconds) of the first pushed sample
+    pub fn start_time(&self) -> f64 {
+        self.start_time
+    }
+
+    /// Estimates the tempo of the complete detection function while it is computed, with the same
+    /// result as get_tempo after the last block
+    pub fn estimate_tempo(mut self) -> StreamingOnsetDetector {
+        self.tempo = Some(TempoEstimator::new(self.axis.sample_rate, self.axis.hop_size));
+        self
+    }
+
+    /// Keeps only the given number of the most recent detection function values, so that memory
+    /// stays bounded
+    pub fn keep_recent_detection_function(mut self, frames: usize) -> StreamingOnsetDetector {
+        self.detection_function = Some(Vec::with_capacity(2 * frames));
+        self.detection_function_limit = Some(frames);
+        self
+    }
+
+    /// Processes a block of samples; returns the times (in seconds) of the onsets that have been
+    /// found with this block
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f64> {
+        let window_size = self.axis.window_size;
+        self.samples.extend_from_slice(samples);
+        self.sample_count += samples.len();
+
+        // Only the samples needed for reflecting the signal at its end are kept
+        self.tail.extend_from_slice(&samples[samples.len().saturating_sub(window_size)..]);
+        let tail_len = window_size - window_size / 2 + 1;
+        if self.tail.len() > tail_len {
+            self.tail.drain(..self.tail.len() - tail_len);
+        }
+
+        // The padding at the start can be computed as soon as enough samples for reflecting are known
+        if !self.started && self.samples.len() > window_size / 2 {
+            self.pad_start();
+        }
+        if self.started {
+            while self.samples.len() >= window_size {
+                let frame = self.transform.transform(&self.samples[..window_size]);
+                self.samples.drain(..self.axis.hop_size);
+                self.push_frame(&frame);
+            }
+        }
+        self.pick(false)
+    }
+
+    /// Processes the remaining samples after the last block; returns the last onset times
+    pub fn finish(&mut self) -> Vec<f64> {
+        let window_size = self.axis.window_size;
+        if !self.started {
+            self.pad_start();
+        }
+        let padding = self.padding.after(&self.tail, window_size - window_size / 2);
+        self.samples.extend(padding);
+
+        // Like the stft, there is one frame per hop size and one for the start
+        while self.received < 1 + self.sample_count / self.axis.hop_size {
+            let frame = self.transform.transform(&self.samples[..window_size.min(self.samples.len())]);
+            self.samples.drain(..self.axis.hop_size.min(self.samples.len()));
+            self.push_frame(&frame);
+        }
+        self.samples.clear();
+        self.pick(true)
+    }
+
+    fn pad_start(&mut self) {
+        let mut samples = self.padding.before(&self.samples, self.axis.window_size / 2);
+        samples.append(&mut self.samples);
+        self.samples = samples;
+        self.started = true;
+    }
+
+    /// Index of the onset that is the first local maximum of all onsets (see PeakPicker::pick)
+    pub fn highest_first_beat(&self) -> Option<usize> {
+        self.highest_first_beat
+    }
+
+    /// The most recent values of the detection function, if they are kept
+    pub fn detection_function(&self) -> Option<WinVec<f32>> {
+        self.detection_function.as_ref().map(|data| {
+            let start = self.detection_function_limit.map_or(0, |limit| data.len().saturating_sub(limit));
+            self.axis.set_data(data[start..].to_owned())
+        })
+    }
+
+    /// The two most likely tempi of the frames so far, if the tempo is estimated
+    pub fn tempo(&self) -> Option<Result<(Tempo, Tempo)>> {
+        self.tempo.as_ref().map(TempoEstimator::tempo)
+    }
+
+    /// Number of pushed samples it takes at most until an onset is reported, counted from the
+    /// onset time: the peak picking needs the following frames, and the last of them has to be
+    /// filled up to its end
+    pub fn latency_samples(&self) -> usize {
+        let window_size = self.axis.window_size;
+        self.picker.lookahead() * self.axis.hop_size + window_size - window_size / 2
+    }
+
+    /// Index of the frame whose centre is closest to the given time (in seconds) after the first
+    /// pushed sample
+    pub fn frame_at(&self, seconds: f64) -> usize {
+        self.axis.frame_at(seconds)
+    }
+
+    /// Number of detection function values computed so far
+    pub fn frame_count(&self) -> usize {
+        self.received
+    }
+
+    /// Number of samples and values held in memory
+    #[cfg(test)]
+    fn stored_values(&self) -> usize {
+        self.samples.capacity() + self.tail.capacity()
+            + self.previous.iter().map(Vec::capacity).sum::<usize>()
+            + self.detection_function.as_ref().map_or(0, Vec::capacity)
+            + self.tempo.as_ref().map_or(0, TempoEstimator::stored_values)
+            + self.values.capacity() + self.peaks.capacity()
+    }
+
+    fn push_frame(&mut self, frame: &[Complex<f32>]) {
+        let log_mel = self.lfsf.log_mel_frame(&self.filterbank, frame);
+        let value = LFSF::flux(&log_mel, &self.previous[0]);
+        self.previous.pop_front();
+        self.previous.push_back(log_mel);
+
+        if let Some(tempo) = self.tempo.as_mut() {
+            tempo.push(value);
+        }
+        if let Some(detection_function) = self.detection_function.as_mut() {
+            detection_function.push(value);
+            // Old values are removed in batches to avoid shifting the vector for every frame
+            if let Some(limit) = self.detection_function_limit {
+                if detection_function.len() >= 2 * limit {
+                    detection_function.drain(..limit);
+                }
+            }
+        }
+        self.values.push(value);
+        self.received += 1;
+    }
+
+    /// Decides for all frames with enough following values whether they are peaks. After the
+    /// last frame, the windows are cut at the end like in the batch peak picking.
+    fn pick(&mut self, finished: bool) -> Vec<f64> {
+        let context = self.picker.context();
+        let lookahead = self.picker.lookahead();
+        let mut onset_times = Vec::new();
+
+        let mut i = self.offset + self.peaks.len(); // next frame to decide
+        while i < self.received {
+            if !finished && self.received < i + lookahead + 1 {
+                break;
+            }
+
+            // The first and the last frame can not be peaks
+            let is_peak = i > 0 && i + 1 < self.received
+                && self.picker.is_peak(&self.values, &self.peaks, i - self.offset);
+            self.peaks.push(is_peak);
+
+            if is_peak {
+                let value = self.values[i - self.offset];
+                if self.highest_first_beat.is_none() && self.last_peak_value.is_some_and(|last| last > value) {
+                    self.highest_first_beat = Some(self.peak_count - 1);
+                }
+                self.last_peak_value = Some(value);
+                self.peak_count += 1;
+                onset_times.push(self.start_time + self.axis.frame_time(i));
+            }
+            i += 1;
+        }
+
+        // Only the values needed for the windows of the next decisions are kept
+        let new_offset = i.saturating_sub(context).max(self.offset);
+        self.values.drain(..new_offset - self.offset);
+        self.peaks.drain(..new_offset - self.offset);
+        self.offset = new_offset;
+
+        onset_times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::beat_tracking_and_tempo::get_tempo;
+    use crate::helpers::WindowFunction;
+    use crate::onset_algorithms::{OnsetAlgorithm, OnsetInput};
+    use crate::test_signals::{TestSignal, PICKER};
+
+    /// Splits samples into blocks of changing, odd sizes, some smaller than a hop and some larger
+    /// than a resampler chunk
+    fn odd_blocks(samples: &[f32]) -> Vec<&[f32]> {
+        let sizes = [1001, 333, 4097, 1, 2049, 7];
+        let mut blocks = Vec::new();
+        let mut position = 0;
+        for size in sizes.iter().cycle() {
+            if position >= samples.len() {
+                break;
+            }
+            let end = (position + size).min(samples.len());
+            blocks.push(&samples[position..end]);
+            position = end;
+        }
+        blocks
+    }
+
+    fn streaming_detector(sample_rate: u32) -> StreamingOnsetDetector {
+        StreamingOnsetDetector::new(LFSF::default(), PICKER, 1024, 441, WindowFunction::Hamming, sample_rate)
+            .keep_recent_detection_function(1000)
+    }
+
+    /// Detection function and onset times of the batch functions: stft, LFSF and peak picking
+    fn batch_onsets(track: &Track) -> (Vec<f32>, Vec<f64>, Option<usize>) {
+        let input = OnsetInput::from_track(track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, Padding::Zero);
+        let output = LFSF::default().find_onsets(&input);
+        let peaks = PICKER.pick(&output).unwrap();
+        let onset_times = peaks.onset_times(track);
+        (output.result.data, onset_times.onset_times, onset_times.highest_first_beat)
+    }
+
+    #[test]
+    fn sample_blocks_return_the_range_of_the_track() {
+        let (track, _) = TestSignal::click_track(120., 4.).generate();
+        let path = std::env::temp_dir().join("music_not_found_streaming_test.wav");
+        let header = wav_io::new_header(track.sample_rate, 16, false, true);
+        wav_io::write_to_file(&mut File::create(&path).unwrap(), &header, &track.channels[0]).unwrap();
+        let open = || SampleBlocks::open(&path, Downmix::Mid, 44100).unwrap();
+
+        let mut blocks = open().range(Some(1.5), Some(3.)).unwrap();
+        assert_eq!(blocks.start_time(), 1.5);
+        let mut samples = Vec::new();
+        while let Some(block) = blocks.next_block().unwrap() {
+            samples.extend(block);
+        }
+        let sliced = Track::from_path(&path).unwrap().slice(Some(1.5), Some(3.)).unwrap();
+        assert_eq!(samples, sliced.channels[0]);
+
+        // Empty and reversed ranges fail at once, ranges after the end when reading
+        assert!(matches!(open().range(Some(2.), Some(2.)), Err(Error::InvalidOptions(_))));
+        assert!(matches!(open().range(Some(3.), Some(1.)), Err(Error::InvalidOptions(_))));
+        let mut blocks = open().range(Some(9.), None).unwrap();
+        assert!(matches!(blocks.next_block(), Err(Error::InvalidOptions(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn streaming_resampler_matches_track_resample() {
+        let (left, _) = TestSignal { sample_rate: 48000, ..TestSignal::drum_pattern(110., 3.) }.generate();
+        let (right, _) = TestSignal { sample_rate: 48000, ..TestSignal::click_track(90., 3.) }.generate();
+        let channels = vec![left.channels[0].clone(), right.channels[0].clone()];
+
+        let mut resampler = StreamingResampler::new(48000, 44100, 2).unwrap();
+        let mut streamed = vec![Vec::new(); 2];
+        let blocks: Vec<_> = odd_blocks(&channels[0]).iter().map(|block| block.len()).collect();
+        let mut position = 0;
+        for len in blocks {
+            let block = channels.iter().map(|channel| channel[position..position + len].to_owned()).collect();
+            position += len;
+            for (channel, output) in streamed.iter_mut().zip(resampler.push(block).unwrap()) {
+                channel.extend(output);
+            }
+        }
+        for (channel, output) in streamed.iter_mut().zip(resampler.finish().unwrap()) {
+            channel.extend(output);
+        }
+
+        let track = Track { channels, sample_rate: 48000, original_sample_rate: 48000, start_time: 0. };
+        let resampled = track.resample(44100).unwrap();
+        assert_eq!(streamed, resampled.channels);
+    }
+
+    #[test]
+    fn streaming_onsets_match_batch_onsets() {
+        let (track, _) = TestSignal::drum_pattern(110., 6.).generate();
+        let (detection_function, onsets, highest_first_beat) = batch_onsets(&track);
+        assert!(!onsets.is_empty());
+
+        let mut detector = streaming_detector(track.sample_rate);
+        let mut recent = StreamingOnsetDetector::new(
+            LFSF::default(),
+            PICKER,
+            1024,
+            441,
+            WindowFunction::Hamming,
+            track.sample_rate,
+        )
+            .keep_recent_detection_function(100);
+        let mut streamed = Vec::new();
+        for block in odd_blocks(&track.channels[0]) {
+            streamed.extend(detector.push(block));
+            recent.push(block);
+        }
+        streamed.extend(detector.finish());
+        recent.finish();
+
+        assert_eq!(streamed, onsets);
+        assert_eq!(detector.highest_first_beat(), highest_first_beat);
+        assert_eq!(detector.detection_function().unwrap().data, detection_function);
+        assert_eq!(recent.detection_function().unwrap().data, detection_function[detection_function.len() - 100..]);
+    }
+
+    #[test]
+    fn streaming_onsets_of_resampled_input_match_batch_onsets() {
+        let (track, _) = TestSignal { sample_rate: 48000, ..TestSignal::click_track(120., 5.) }.generate();
+        let samples = track.channels[0].clone();
+        let (detection_function, onsets, _) = batch_onsets(&track.resample(44100).unwrap());
+
+        let mut resampler = StreamingResampler::new(48000, 44100, 1).unwrap();
+        let mut detector = streaming_detector(44100);
+        let mut streamed = Vec::new();
+        for block in odd_blocks(&samples) {
+            let resampled = resampler.push(vec![block.to_owned()]).unwrap();
+            streamed.extend(detector.push(&resampled[0]));
+        }
+        streamed.extend(detector.push(&resampler.finish().unwrap()[0]));
+        streamed.extend(detector.finish());
+
+        assert_eq!(streamed, onsets);
+        assert_eq!(detector.detection_function().unwrap().data, detection_function);
+    }
+
+    #[test]
+    fn streaming_tempo_matches_batch_tempo_with_flat_memory() {
+        let mut stored_values = Vec::new();
+        for seconds in [10., 40.] {
+            let (track, _) = TestSignal::drum_pattern(110., seconds).generate();
+            let input = OnsetInput::from_track(&track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, Padding::Zero);
+            let (tempo1, tempo2) = get_tempo(&LFSF::default().find_onsets(&input).result).unwrap();
+
+            let mut detector = streaming_detector(track.sample_rate).estimate_tempo();
+            let mut most_values = 0;
+            for block in odd_blocks(&track.channels[0]) {
+                detector.push(block);
+                most_values = most_values.max(detector.stored_values());
+            }
+            detector.finish();
+            let (streamed1, streamed2) = detector.tempo().unwrap().unwrap();
+            assert_eq!((streamed1.lag, streamed2.lag), (tempo1.lag, tempo2.lag));
+            stored_values.push(most_values);
+        }
+
+        // A four times longer track needs no more memory
+        assert_eq!(stored_values[0], stored_values[1]);
+    }
+}
@@ -0,0 +1,386 @@
+use std::f32::consts::PI;
+
+use crate::peak_picking::PeakPicker;
+use crate::track::Track;
+
+/// Peak picker of the tests, for detection functions with 100 frames per second: maxima and means
+/// within 7 frames, at least 3 frames between onsets
+pub const PICKER: PeakPicker = PeakPicker {
+    local_window_max: 7,
+    local_window_mean: 7,
+    minimum_distance: 3,
+    delta: 0.1,
+};
+
+/// Sounds a synthetic test signal is made of
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sound {
+    /// A very short, decaying 2 kHz burst
+    Click,
+    /// A sine tone of the given frequency (in Hz) and length (in seconds) with a short attack
+    Tone { frequency: f32, length: f32 },
+    /// A tone with three harmonics whose pitch oscillates by the given depth (in semitones) at the
+    /// given rate (in Hz), like a sung or bowed note
+    Vibrato { frequency: f32, length: f32, rate: f32, depth: f32 },
+    /// A sine sweep from 150 Hz down to 50 Hz, like a bass drum
+    Kick,
+    /// Decaying white noise together with a 200 Hz tone, like a snare drum
+    Snare,
+    /// Short, high-pass filtered noise, like a closed hi-hat
+    HiHat,
+}
+
+/// Describes a synthetic test signal with events at a known tempo. The signal and its exact ground
+/// truth are created by `generate`.
+#[derive(Clone, Debug)]
+pub struct TestSignal {
+    pub sample_rate: u32,
+    /// Length of the signal (in seconds)
+    pub duration: f64,
+    /// Time (in seconds) of the first beat
+    pub first_beat: f64,
+    /// Tempo changes as (time in seconds, tempo in BPM); a change takes effect at the first beat at
+    /// or after its time. The first entry is the initial tempo.
+    pub tempo: Vec<(f64, f64)>,
+    /// Number of events per beat
+    pub events_per_beat: usize,
+    /// Position of every second event between its neighbours: 0.5 is straight, 2/3 triplet swing
+    pub swing: f64,
+    /// Number of beats per bar; the first beat of each bar is accented
+    pub beats_per_bar: usize,
+    /// Sounds of the events, repeated over and over
+    pub sounds: Vec<Sound>,
+}
+
+/// The exact times of all events of a test signal
+#[derive(Clone, Debug, Default)]
+pub struct GroundTruth {
+    /// Start times (in seconds) of all events
+    pub onsets: Vec<f64>,
+    /// Sound of each onset
+    pub sounds: Vec<Sound>,
+    /// Times (in seconds) of all beats
+    pub beats: Vec<f64>,
+}
+
+impl TestSignal {
+    /// Clicks on every beat at a constant tempo
+    pub fn click_track(bpm: f64, duration: f64) -> TestSignal {
+        TestSignal {
+            sample_rate: 44100,
+            duration,
+            first_beat: 0.5,
+            tempo: vec![(0., bpm)],
+            events_per_beat: 1,
+            swing: 0.5,
+            beats_per_bar: 4,
+            sounds: vec![Sound::Click],
+        }
+    }
+
+    /// Tone bursts of the given frequencies, one on every beat
+    pub fn tone_bursts(bpm: f64, duration: f64, frequencies: &[f32]) -> TestSignal {
+        TestSignal {
+            sounds: frequencies.iter().map(|&frequency| Sound::Tone { frequency, length: 0.2 }).collect(),
+            ..TestSignal::click_track(bpm, duration)
+        }
+    }
+
+    /// Long notes of the given frequencies with a strong vibrato, one on every beat
+    pub fn vibrato_notes(bpm: f64, duration: f64, frequencies: &[f32]) -> TestSignal {
+        let length = 0.8 * 60. / bpm as f32;
+        TestSignal {
+            sounds: frequencies
+                .iter()
+                .map(|&frequency| Sound::Vibrato { frequency, length, rate: 6., depth: 1. })
+                .collect(),
+            ..TestSignal::click_track(bpm, duration)
+        }
+    }
+
+    /// A rock beat: kick and snare alternating on the beats, hi-hats on the eighth notes in between
+    pub fn drum_pattern(bpm: f64, duration: f64) -> TestSignal {
+        TestSignal {
+            events_per_beat: 2,
+            sounds: vec![Sound::Kick, Sound::HiHat, Sound::Snare, Sound::HiHat],
+            ..TestSignal::click_track(bpm, duration)
+        }
+    }
+
+    /// Changes the tempo at the given time (in seconds)
+    pub fn with_tempo_change(mut self, time: f64, bpm: f64) -> TestSignal {
+        self.tempo.push((time, bpm));
+        self
+    }
+
+    /// Delays every second event, see `swing`
+    pub fn with_swing(mut self, swing: f64) -> TestSignal {
+        self.swing = swing;
+        self
+    }
+
+    /// Synthesises the signal as mono track together with its ground truth
+    pub fn generate(&self) -> (Track, GroundTruth) {
+        let mut samples = vec![0f32; (self.duration * self.sample_rate as f64).round() as usize];
+        let mut ground_truth = GroundTruth::default();
+        let mut noise = Noise::new();
+
+        let mut beat = self.first_beat;
+        let mut beat_index = 0;
+        while beat < self.duration {
+            let bpm = self
+                .tempo
+                .iter()
+                .rfind(|(time, _)| *time <= beat + 1e-9)
+                .map_or(120., |&(_, bpm)| bpm);
+            let period = 60. / bpm;
+            ground_truth.beats.push(beat);
+
+            for event in 0..self.events_per_beat {
+                // Every second event is shifted by the swing
+                let position = if event % 2 == 1 { (event - 1) as f64 + 2. * self.swing } else { event as f64 };
+                let time = beat + period * position / self.events_per_beat as f64;
+                if time >= self.duration {
+                    break;
+                }
+
+                let sound = self.sounds[ground_truth.onsets.len() % self.sounds.len()];
+                let amplitude = match (event, beat_index % self.beats_per_bar.max(1)) {
+                    (0, 0) => 0.9,
+                    (0, _) => 0.6,
+                    _ => 0.4,
+                };
+                let start = (time * self.sample_rate as f64).round() as usize;
+                for (sample, value) in samples[start..].iter_mut().zip(sound.render(self.sample_rate, &mut noise)) {
+                    *sample += amplitude * value;
+                }
+
+                ground_truth.onsets.push(time);
+                ground_truth.sounds.push(sound);
+            }
+
+            beat += period;
+            beat_index += 1;
+        }
+
+        let track = Track {
+            channels: vec![samples],
+            sample_rate: self.sample_rate,
+            original_sample_rate: self.sample_rate,
+            start_time: 0.,
+        };
+        (track, ground_truth)
+    }
+}
+
+/// A sine of constant amplitude whose frequency changes on every beat, cycling through the given
+/// frequencies. The phase is continuous, so the pitch changes are the only onsets.
+pub fn frequency_steps(bpm: f64, duration: f64, frequencies: &[f32]) -> (Track, GroundTruth) {
+    let sample_rate = 44100;
+    let period = 60. / bpm;
+    let mut ground_truth = GroundTruth::default();
+
+    let mut beat = 0.5;
+    while beat < duration {
+        ground_truth.beats.push(beat);
+        ground_truth.onsets.push(beat);
+        let frequency = frequencies[ground_truth.sounds.len() % frequencies.len()];
+        ground_truth.sounds.push(Sound::Tone { frequency, length: period as f32 });
+        beat += period;
+    }
+
+    let mut phase = 0f32;
+    let mut step = 0;
+    let samples = (0..(duration * sample_rate as f64).round() as usize)
+        .map(|i| {
+            // Before the first change, the last frequency is played
+            let time = i as f64 / sample_rate as f64;
+            while step < ground_truth.onsets.len() && ground_truth.onsets[step] <= time {
+                step += 1;
+            }
+            let frequency = frequencies[(step + frequencies.len() - 1) % frequencies.len()];
+            phase = (phase + 2. * PI * frequency / sample_rate as f32) % (2. * PI);
+            0.5 * phase.sin()
+        })
+        .collect();
+
+    let track = Track {
+        channels: vec![samples],
+        sample_rate,
+        original_sample_rate: sample_rate,
+        start_time: 0.,
+    };
+    (track, ground_truth)
+}
+
+impl Sound {
+    /// Synthesises the sound, starting with its onset
+    fn render(&self, sample_rate: u32, noise: &mut Noise) -> Vec<f32> {
+        let sample_rate = sample_rate as f32;
+        let length = |seconds: f32| (seconds * sample_rate) as usize;
+        let decay = |t: f32, time_constant: f32| (-t / time_constant).exp();
+
+        match *self {
+            Sound::Click => (0..length(0.02))
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    (2. * PI * 2000. * t).sin() * decay(t, 0.003)
+                })
+                .collect(),
+            Sound::Tone { frequency, length: seconds } => {
+                // The slow release avoids a broadband transient at the end of the tone
+                let attack = length(0.005) as f32;
+                let release = length(0.05) as f32;
+                let samples = length(seconds);
+                (0..samples)
+                    .map(|i| {
+                        let t = i as f32 / sample_rate;
+                        let envelope = (i as f32 / attack).min((samples - i) as f32 / release).min(1.);
+                        (2. * PI * frequency * t).sin() * envelope
+                    })
+                    .collect()
+            }
+            Sound::Vibrato { frequency, length: seconds, rate, depth } => {
+                let attack = length(0.005) as f32;
+                let release = length(0.05) as f32;
+                let samples = length(seconds);
+                let mut phase = 0.;
+                (0..samples)
+                    .map(|i| {
+                        let t = i as f32 / sample_rate;
+                        let envelope = (i as f32 / attack).min((samples - i) as f32 / release).min(1.);
+                        let pitch = frequency * 2f32.powf(depth / 12. * (2. * PI * rate * t).sin());
+                        phase += 2. * PI * pitch / sample_rate;
+                        let harmonics = phase.sin() + 0.5 * (2. * phase).sin() + 0.25 * (3. * phase).sin();
+                        harmonics / 1.75 * envelope
+                    })
+                    .collect()
+            }
+            Sound::Kick => {
+                let mut phase = 0.;
+                (0..length(0.3))
+                    .map(|i| {
+                        let t = i as f32 / sample_rate;
+                        let frequency = 50. + 100. * decay(t, 0.04);
+                        phase += 2. * PI * frequency / sample_rate;
+                        phase.sin() * decay(t, 0.08)
+                    })
+                    .collect()
+            }
+            Sound::Snare => (0..length(0.2))
+                .map(|i| {
+                    let t = i as f32 / sample_rate;
+                    (0.7 * noise.next() + 0.3 * (2. * PI * 200. * t).sin()) * decay(t, 0.04)
+                })
+                .collect(),
+            Sound::HiHat => {
+                // The difference of successive noise samples removes most of the low frequencies
+                let mut previous = 0.;
+                (0..length(0.05))
+                    .map(|i| {
+                        let t = i as f32 / sample_rate;
+                        let current = noise.next();
+                        let value = 0.5 * (current - previous);
+                        previous = current;
+                        value * decay(t, 0.01)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Deterministic white noise (xorshift), so that test signals are always the same
+struct Noise {
+    state: u32,
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise { state: 0x9E37_79B9 }
+    }
+
+    /// The next noise value in [-1, 1)
+    fn next(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2. - 1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beat_tracking_and_tempo::{get_beats, get_tempo};
+    use crate::constants::{BEAT_ACCURACY, ONSET_ACCURACY, TEMPO_DEVIATION};
+    use crate::f_measure::f_measure;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::onset_algorithms::{OnsetAlgorithm, OnsetInput, OnsetOutput, LFSF};
+    use crate::track::Downmix;
+
+    fn lfsf(track: &Track) -> OnsetOutput {
+        let input = OnsetInput::from_track(track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, Padding::Zero);
+        LFSF { log_lambda: 0.7, ..LFSF::default() }.find_onsets(&input)
+    }
+
+    fn onsets(track: &Track) -> Vec<f64> {
+        PICKER.pick(&lfsf(track)).unwrap().onset_times(track).onset_times
+    }
+
+    #[test]
+    fn ground_truth_follows_tempo_changes_and_swing() {
+        let (track, truth) = TestSignal::click_track(120., 4.).with_tempo_change(2., 60.).generate();
+        assert_eq!(track.len(), 4 * 44100);
+        assert_eq!(truth.beats, vec![0.5, 1., 1.5, 2., 3.]);
+
+        let (_, truth) = TestSignal::drum_pattern(120., 1.5).with_swing(2. / 3.).generate();
+        let expected = [0.5, 0.5 + 1. / 3., 1., 1. + 1. / 3.];
+        assert_eq!(truth.onsets.len(), expected.len());
+        for (onset, expected) in truth.onsets.iter().zip(expected) {
+            assert!((onset - expected).abs() < 1e-9);
+        }
+        assert_eq!(truth.sounds, vec![Sound::Kick, Sound::HiHat, Sound::Snare, Sound::HiHat]);
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let (first, _) = TestSignal::drum_pattern(100., 3.).generate();
+        let (second, _) = TestSignal::drum_pattern(100., 3.).generate();
+        assert_eq!(first.channels, second.channels);
+    }
+
+    #[test]
+    fn lfsf_finds_onsets_of_all_sounds() {
+        let signals = [
+            TestSignal::click_track(120., 10.),
+            TestSignal::tone_bursts(100., 10., &[440., 660., 880.]),
+            TestSignal::drum_pattern(110., 10.),
+            TestSignal::drum_pattern(90., 10.).with_swing(2. / 3.),
+        ];
+        for signal in signals {
+            let (track, truth) = signal.generate();
+            let result = f_measure(&onsets(&track), &truth.onsets, ONSET_ACCURACY);
+            assert!(result.f_measure > 0.9, "{:?}: {}", signal.sounds, result.f_measure);
+        }
+    }
+
+    #[test]
+    fn tempo_and_beats_of_click_track() {
+        let (track, truth) = TestSignal::click_track(120., 20.).generate();
+        let output = lfsf(&track);
+        let (tempo1, tempo2) = get_tempo(&output.result).unwrap();
+        assert!(
+            (tempo1.bpm - 120.).abs() <= 120. * TEMPO_DEVIATION || (tempo2.bpm - 120.).abs() <= 120. * TEMPO_DEVIATION,
+            "{} {}",
+            tempo1.bpm,
+            tempo2.bpm
+        );
+
+        let tempo = if (tempo1.bpm - 120.).abs() < (tempo2.bpm - 120.).abs() { tempo1 } else { tempo2 };
+        let onset_times = PICKER.pick(&output).unwrap().onset_times(&track);
+        let beats = get_beats(tempo, &onset_times.onset_times, onset_times.highest_first_beat).unwrap();
+        let result = f_measure(&beats.beats, &truth.beats, BEAT_ACCURACY);
+        assert!(result.f_measure > 0.9, "{}", result.f_measure);
+    }
+}
@@ -1,17 +1,276 @@
-use std::{path::Path, fs::File};
-use wav_io::header::WavHeader;
+use std::path::Path;
 
-/// Structure holding the content of WAV files
+use rubato::{FftFixedIn, Resampler};
+
+use crate::constants::RESAMPLER_CHUNK_SIZE;
+use crate::decoder::AudioFormat;
+use crate::error::{Error, Result};
+
+/// Structure holding the content of audio files
 pub struct Track {
-    pub samples: Vec<f32>,
-    pub header: WavHeader
+    /// The samples of each channel (not interleaved); all channels have the same length
+    pub channels: Vec<Vec<f32>>,
+    /// The sample rate of the samples in `channels`
+    pub sample_rate: u32,
+    /// The sample rate of the file the track has been read from, before any resampling
+    pub original_sample_rate: u32,
+    /// Time (in seconds) of the first sample within the file, if only a part of it is analysed
+    pub start_time: f64,
+}
+
+/// Strategies for reducing the channels of a track to a single signal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Downmix {
+    /// Average of all channels
+    Mid,
+    /// Only the first channel
+    Left,
+    /// Only the second channel (the first one for mono tracks)
+    Right,
+    /// The channel with the highest energy
+    MaxEnergy,
+}
+
+impl Downmix {
+    /// Parses the name of a downmix strategy as used on the command line
+    pub fn from_name(name: &str) -> Option<Downmix> {
+        match name {
+            "mid" => Some(Downmix::Mid),
+            "left" => Some(Downmix::Left),
+            "right" => Some(Downmix::Right),
+            "max-energy" => Some(Downmix::MaxEnergy),
+            _ => None,
+        }
+    }
 }
 
 impl Track {
-    /// Create a new Track from a WAV file at the given path
-    pub fn from_path(file_path: &Path) -> Track {
-        let input_file = File::open(&file_path).unwrap();
-        let (header, samples) = wav_io::read_from_file(input_file).unwrap();
-        Track { samples,header }
+    /// Create a new Track from an audio file (WAV, FLAC, OGG Vorbis, MP3 or AIFF) at the given path
+    pub fn from_path(file_path: &Path) -> Result<Track> {
+        let format = AudioFormat::detect(file_path).ok_or(Error::UnsupportedFormat)?;
+        let mut decoded = format.decoder().decode(file_path)?;
+        // Channels only differ in length if the decoder returns a partial last packet; all are cut to
+        // the shortest one
+        let len = decoded.channels.iter().map(|channel| channel.len()).min().unwrap_or(0);
+        decoded.channels.iter_mut().for_each(|channel| channel.truncate(len));
+        let track = Track {
+            channels: decoded.channels,
+            sample_rate: decoded.sample_rate,
+            original_sample_rate: decoded.sample_rate,
+            start_time: 0.,
+        };
+
+        if track.is_empty() {
+            return Err(Error::EmptyTrack);
+        }
+        Ok(track)
+    }
+
+    /// Converts all channels to the given sample rate. The original sample rate is kept.
+    pub fn resample(self, sample_rate: u32) -> Result<Track> {
+        if sample_rate == self.sample_rate || self.is_empty() {
+            return Ok(Track { sample_rate, ..self });
+        }
+
+        let mut resampler = FftFixedIn::<f32>::new(
+            self.sample_rate as usize,
+            sample_rate as usize,
+            RESAMPLER_CHUNK_SIZE,
+            2,
+            self.channel_count(),
+        )?;
+
+        // The resampler delays its output; the delayed part is cut off at the end
+        let delay = resampler.output_delay();
+        let expected_len = (self.len() as f64 * sample_rate as f64 / self.sample_rate as f64).round() as usize;
+
+        let mut channels: Vec<Vec<f32>> = vec![Vec::with_capacity(delay + expected_len); self.channel_count()];
+        fn append(channels: &mut [Vec<f32>], output: Vec<Vec<f32>>) {
+            for (channel, output_channel) in channels.iter_mut().zip(output) {
+                channel.extend(output_channel);
+            }
+        }
+
+        let mut position = 0;
+        while position + resampler.input_frames_next() <= self.len() {
+            let next_position = position + resampler.input_frames_next();
+            let chunk: Vec<&[f32]> = self.channels.iter().map(|c| &c[position..next_position]).collect();
+            append(&mut channels, resampler.process(&chunk, None)?);
+            position = next_position;
+        }
+
+        // The remaining samples do not fill a whole chunk; afterwards, the resampler is flushed
+        let rest: Vec<&[f32]> = self.channels.iter().map(|c| &c[position..]).collect();
+        append(&mut channels, resampler.process_partial(Some(&rest), None)?);
+        while channels[0].len() < delay + expected_len {
+            append(&mut channels, resampler.process_partial::<&[f32]>(None, None)?);
+        }
+
+        for channel in channels.iter_mut() {
+            channel.drain(..delay);
+            channel.truncate(expected_len);
+        }
+
+        Ok(Track {
+            channels,
+            sample_rate,
+            original_sample_rate: self.original_sample_rate,
+            start_time: self.start_time,
+        })
+    }
+
+    /// Keeps only the samples between the given times (in seconds, measured from the start of the
+    /// file); without a start or end, the track is kept from its beginning or until its end
+    pub fn slice(self, start: Option<f64>, end: Option<f64>) -> Result<Track> {
+        if start.is_none() && end.is_none() {
+            return Ok(self);
+        }
+
+        let to_index = |time: f64| ((time - self.start_time).max(0.) * self.sample_rate as f64).round() as usize;
+        let start_index = start.map_or(0, to_index).min(self.len());
+        let end_index = end.map_or(self.len(), to_index).min(self.len());
+        if start_index >= end_index {
+            return Err(Error::InvalidOptions(format!(
+                "the time range {}..{} s does not contain any samples of the track",
+                start.unwrap_or(self.start_time),
+                end.map_or("end".to_owned(), |end| end.to_string()),
+            )));
+        }
+
+        Ok(Track {
+            channels: self
+                .channels
+                .into_iter()
+                .map(|channel| channel[start_index..end_index].to_owned())
+                .collect(),
+            sample_rate: self.sample_rate,
+            original_sample_rate: self.original_sample_rate,
+            start_time: self.start_time + start_index as f64 / self.sample_rate as f64,
+        })
+    }
+
+    /// Number of channels of the track
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Number of samples per channel
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, |channel| channel.len())
+    }
+
+    /// Checks whether the track contains no samples
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reduces all channels to a single signal using the given strategy
+    pub fn downmix(&self, downmix: Downmix) -> Vec<f32> {
+        match downmix {
+            Downmix::Mid => {
+                let channel_count = self.channel_count() as f32;
+                (0..self.len())
+                    .map(|i| self.channels.iter().map(|channel| channel[i]).sum::<f32>() / channel_count)
+                    .collect()
+            }
+            Downmix::Left => self.channels[0].to_owned(),
+            Downmix::Right => self.channels[1.min(self.channel_count() - 1)].to_owned(),
+            Downmix::MaxEnergy => {
+                let energy = |channel: &Vec<f32>| channel.iter().map(|x| x * x).sum::<f32>();
+                self.channels
+                    .iter()
+                    .max_by(|a, b| energy(a).total_cmp(&energy(b)))
+                    .expect("a track has at least one channel")
+                    .to_owned()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{Padding, WindowFunction};
+    use crate::onset_algorithms::{OnsetAlgorithm, OnsetInput, LFSF};
+    use crate::test_signals::{TestSignal, PICKER};
+
+    fn onset_times(track: &Track) -> Vec<f64> {
+        let input = OnsetInput::from_track(track, Downmix::Mid, 1024, 441, WindowFunction::Hamming, Padding::Zero);
+        PICKER.pick(&LFSF::default().find_onsets(&input)).unwrap().onset_times(track).onset_times
+    }
+
+    #[test]
+    fn downmix_strategies() {
+        let track = Track {
+            channels: vec![vec![1., -1., 0.5], vec![3., 1., -0.5]],
+            sample_rate: 44100,
+            original_sample_rate: 44100,
+            start_time: 0.,
+        };
+        assert_eq!(track.downmix(Downmix::Mid), vec![2., 0., 0.]);
+        assert_eq!(track.downmix(Downmix::Left), vec![1., -1., 0.5]);
+        assert_eq!(track.downmix(Downmix::Right), vec![3., 1., -0.5]);
+        assert_eq!(track.downmix(Downmix::MaxEnergy), vec![3., 1., -0.5]);
+
+        // All strategies work on mono tracks, where they keep the only channel
+        let mono = Track { channels: vec![vec![1., -1.]], sample_rate: 44100, original_sample_rate: 44100, start_time: 0. };
+        for downmix in [Downmix::Mid, Downmix::Left, Downmix::Right, Downmix::MaxEnergy] {
+            assert_eq!(mono.downmix(downmix), vec![1., -1.], "{:?}", downmix);
+        }
+        assert_eq!(Downmix::from_name("max-energy"), Some(Downmix::MaxEnergy));
+        assert_eq!(Downmix::from_name("side"), None);
+    }
+
+    #[test]
+    fn resampling_keeps_duration_and_onset_times() {
+        let signal = TestSignal::click_track(120., 6.);
+        let (native, truth) = signal.generate();
+        let (track, _) = TestSignal { sample_rate: 48000, ..signal }.generate();
+        assert_eq!(track.len(), 6 * 48000);
+
+        let resampled = track.resample(44100).unwrap();
+        assert_eq!(resampled.sample_rate, 44100);
+        assert_eq!(resampled.original_sample_rate, 48000);
+        assert_eq!(resampled.len(), native.len());
+
+        // The delay of the resampler is removed, so onsets stay where they are
+        let onsets = onset_times(&resampled);
+        assert_eq!(onsets.len(), truth.onsets.len());
+        for (onset, native_onset) in onsets.iter().zip(onset_times(&native)) {
+            assert!((onset - native_onset).abs() <= 0.01, "{} {}", onset, native_onset);
+        }
+    }
+
+    #[test]
+    fn sliced_onsets_are_relative_to_the_file() {
+        let (track, _) = TestSignal::click_track(120., 8.).generate();
+        let full_onsets = onset_times(&track);
+
+        // The range starts at a multiple of the hop size, so the frames of both tracks line up
+        let (start, end) = (2.25, 6.25);
+        let sliced = track.slice(Some(start), Some(end)).unwrap();
+        assert_eq!(sliced.start_time, start);
+        assert_eq!(sliced.len(), 4 * 44100);
+
+        let expected: Vec<f64> = full_onsets.into_iter().filter(|&time| start <= time && time < end).collect();
+        assert_eq!(expected.len(), 8);
+        assert_eq!(onset_times(&sliced), expected);
+
+        // Slicing a slice is relative to the file, too
+        let twice = sliced.slice(Some(3.25), None).unwrap();
+        assert_eq!((twice.start_time, twice.len()), (3.25, 3 * 44100));
+    }
+
+    #[test]
+    fn empty_or_reversed_ranges_are_rejected() {
+        for (start, end) in [(Some(2.), Some(2.)), (Some(3.), Some(1.)), (Some(9.), None), (None, Some(0.))] {
+            let (track, _) = TestSignal::click_track(120., 4.).generate();
+            assert!(
+                matches!(track.slice(start, end), Err(Error::InvalidOptions(_))),
+                "{:?} {:?}",
+                start,
+                end
+            );
+        }
     }
 }